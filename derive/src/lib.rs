@@ -0,0 +1,232 @@
+//! `#[derive(Eip712)]` for the `Eip712Struct` trait of `eip_712_utils`
+//!
+//! ```ignore
+//! #[derive(Eip712)]
+//! #[eip712(name = "NFTData", rename_all = "camelCase")]
+//! struct NftData {
+//!     token_id: U256,
+//!     #[eip712(type = "uint64")]
+//!     nonce: u64,
+//! }
+//! ```
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields, LitStr, Result};
+
+#[proc_macro_derive(Eip712, attributes(eip712))]
+pub fn derive_eip712(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// `#[eip712(...)]` on the struct
+#[derive(Default)]
+struct ContainerAttrs {
+    name: Option<String>,
+    camel_case: bool,
+}
+
+/// `#[eip712(...)]` on a field
+#[derive(Default)]
+struct FieldAttrs {
+    type_: Option<LitStr>,
+    rename: Option<String>,
+}
+
+fn parse_container_attrs(input: &DeriveInput) -> Result<ContainerAttrs> {
+    let mut attrs = ContainerAttrs::default();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("eip712"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                attrs.name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("rename_all") {
+                let rule = meta.value()?.parse::<LitStr>()?;
+                if rule.value() != "camelCase" {
+                    return Err(Error::new(rule.span(), "only \"camelCase\" is supported"));
+                }
+                attrs.camel_case = true;
+            } else {
+                return Err(meta.error("expected `name` or `rename_all`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attrs)
+}
+
+fn parse_field_attrs(field: &syn::Field) -> Result<FieldAttrs> {
+    let mut attrs = FieldAttrs::default();
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("eip712"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("type") {
+                attrs.type_ = Some(meta.value()?.parse::<LitStr>()?);
+            } else if meta.path.is_ident("rename") {
+                attrs.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta.error("expected `type` or `rename`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(attrs)
+}
+
+/// the signedness and width of an integer type such as `uint48`
+fn parse_integer_type(type_: &str) -> Option<(bool, usize)> {
+    let (signed, bits) = match type_.strip_prefix("uint") {
+        Some(bits) => (false, bits),
+        None => (true, type_.strip_prefix("int")?),
+    };
+    if bits.starts_with('0') {
+        return None;
+    }
+    match bits.parse::<usize>() {
+        Ok(bits) if bits <= 256 && bits % 8 == 0 => Some((signed, bits)),
+        _ => None,
+    }
+}
+
+fn camel_case(ident: &str) -> String {
+    let mut parts = ident.split('_').filter(|part| !part.is_empty());
+    let mut camel = parts.next().unwrap_or_default().to_owned();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2> {
+    let container = parse_container_attrs(&input)?;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new(input.span(), "Eip712 requires named fields")),
+        },
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "Eip712 can only be derived for structs",
+            ))
+        }
+    };
+
+    let ident = &input.ident;
+    let type_name = container.name.unwrap_or_else(|| ident.to_string());
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut field_types = vec![];
+    let mut dependencies = vec![];
+    let mut words = vec![];
+    let mut checks = vec![];
+    for field in fields {
+        let attrs = parse_field_attrs(field)?;
+        let field_ident = field.ident.as_ref().expect("named fields; qed");
+        let field_ty = &field.ty;
+        let name = attrs.rename.unwrap_or_else(|| {
+            let name = field_ident.to_string();
+            let name = name.trim_start_matches("r#");
+            if container.camel_case {
+                camel_case(name)
+            } else {
+                name.to_owned()
+            }
+        });
+        let type_ = match attrs.type_ {
+            // only integers can be widened without changing their encoding
+            Some(type_) => {
+                let (signed, bits) = parse_integer_type(&type_.value()).ok_or_else(|| {
+                    Error::new(
+                        type_.span(),
+                        "`type` can only widen an integer field, e.g. \"uint256\"",
+                    )
+                })?;
+                let message = format!(
+                    "#[eip712(type = \"{}\")] needs an integer field of the same signedness that is at most {} bits wide",
+                    type_.value(),
+                    bits
+                );
+                checks.push(quote! {
+                    ::std::assert!(
+                        <#field_ty as ::eip_712_utils::typed::Eip712Integer>::SIGNED == #signed
+                            && <#field_ty as ::eip_712_utils::typed::Eip712Integer>::BITS <= #bits,
+                        #message
+                    );
+                });
+                quote!(#type_.to_owned())
+            }
+            None => quote!(<#field_ty as ::eip_712_utils::typed::Eip712Type>::field_type()),
+        };
+
+        field_types.push(quote! {
+            ::eip_712_utils::FieldType {
+                name: #name.to_owned(),
+                type_: #type_,
+            }
+        });
+        dependencies.push(quote! {
+            <#field_ty as ::eip_712_utils::typed::Eip712Type>::add_field_types(types);
+        });
+        words.push(quote! {
+            ::eip_712_utils::typed::Eip712Type::encode_word(&self.#field_ident)
+        });
+    }
+
+    // evaluated when `fields` is instantiated, so a mismatched override fails to compile
+    Ok(quote! {
+        impl #impl_generics #ident #ty_generics #where_clause {
+            #[doc(hidden)]
+            const __EIP712_TYPE_OVERRIDES: () = { #(#checks)* };
+        }
+
+        impl #impl_generics ::eip_712_utils::typed::Eip712Struct for #ident #ty_generics #where_clause {
+            fn type_name() -> ::std::string::String {
+                #type_name.to_owned()
+            }
+
+            fn fields() -> ::std::vec::Vec<::eip_712_utils::FieldType> {
+                let () = Self::__EIP712_TYPE_OVERRIDES;
+                vec![#(#field_types),*]
+            }
+
+            fn add_dependencies(types: &mut ::eip_712_utils::MessageTypes) {
+                #(#dependencies)*
+            }
+
+            fn encode_data(&self) -> ::std::vec::Vec<::eip_712_utils::__private::H256> {
+                vec![#(#words),*]
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_integer_types() {
+        assert_eq!(parse_integer_type("uint8"), Some((false, 8)));
+        assert_eq!(parse_integer_type("uint256"), Some((false, 256)));
+        assert_eq!(parse_integer_type("int48"), Some((true, 48)));
+        for type_ in [
+            "uint", "uint0", "uint08", "uint7", "uint264", "bytes32", "address", "Uint8",
+        ] {
+            assert_eq!(parse_integer_type(type_), None, "{}", type_);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use eip_712_utils::{
+    hash_structured_data, EIP712Domain, Eip712, Eip712Struct, Eip712Type, Uint8, EIP712,
+};
+use ethereum_types::{Address, U256};
+use serde_json::json;
+use std::str::FromStr;
+
+#[derive(Eip712)]
+#[eip712(name = "NFTData", rename_all = "camelCase")]
+struct NftData {
+    token_id: U256,
+    amount: U256,
+    to: Address,
+    #[eip712(type = "uint256")]
+    nonce: u64,
+}
+
+#[derive(Eip712)]
+struct Person {
+    name: String,
+    wallet: Address,
+}
+
+#[derive(Eip712)]
+struct Mail {
+    from: Person,
+    to: Vec<Person>,
+    #[eip712(rename = "contents")]
+    body: String,
+}
+
+#[derive(Eip712)]
+#[eip712(rename_all = "camelCase")]
+struct Order {
+    side: Uint8,
+    #[eip712(type = "uint48")]
+    valid_until: u32,
+    #[eip712(type = "int128")]
+    price_delta: i64,
+}
+
+fn address(hex: &str) -> Address {
+    Address::from_str(hex).unwrap()
+}
+
+#[test]
+fn it_derives_nft_data() {
+    let data = NftData {
+        token_id: 1.into(),
+        amount: 1.into(),
+        to: address("7FA9385bE102ac3EAc297483Dd6233D62b3e1496"),
+        nonce: 1,
+    };
+    let domain = EIP712Domain::new(
+        "AionRisingNFTs",
+        "0.0.1",
+        "0x7A69",
+        "0x037eDa3aDB1198021A9b2e88C22B464fD38db3f3",
+    );
+
+    assert_eq!(
+        NftData::encode_type(),
+        "NFTData(uint256 tokenId,uint256 amount,address to,uint256 nonce)"
+    );
+    assert_eq!(
+        format!("{:x}", hash_structured_data((&domain, &data)).unwrap()),
+        "77915d20c811f39572463a234db9b776d518d07d9682a825be0d79752745a4c7"
+    );
+}
+
+#[test]
+fn it_derives_nested_structs() {
+    let mail = Mail {
+        from: Person {
+            name: "Cow".into(),
+            wallet: address("CD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"),
+        },
+        to: vec![Person {
+            name: "Bob".into(),
+            wallet: address("bBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"),
+        }],
+        body: "Hello, Bob!".into(),
+    };
+    let domain = EIP712Domain::new(
+        "Ether Mail",
+        "1",
+        "0x1",
+        "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC",
+    );
+
+    assert_eq!(
+        Mail::encode_type(),
+        "Mail(Person from,Person[] to,string contents)Person(string name,address wallet)"
+    );
+    assert_eq!(Mail::message_types().len(), 2);
+
+    let mut untyped = EIP712::builder()
+        .domain(
+            "Ether Mail",
+            "1",
+            "0x1",
+            "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC",
+        )
+        .custom_field((Mail::type_name(), Mail::fields()))
+        .message(json!({
+            "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+            "to": [{ "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" }],
+            "contents": "Hello, Bob!"
+        }))
+        .build();
+    untyped.add_type(Person::type_name(), Person::fields());
+
+    assert_eq!(
+        hash_structured_data((&domain, &mail)).unwrap(),
+        hash_structured_data(untyped).unwrap()
+    );
+}
+
+#[test]
+fn it_widens_integer_fields() {
+    let order = Order {
+        side: Uint8(1),
+        valid_until: 1_700_000_000,
+        price_delta: -5,
+    };
+
+    assert_eq!(
+        Order::encode_type(),
+        "Order(uint8 side,uint48 validUntil,int128 priceDelta)"
+    );
+    // widening keeps the word, only the type string changes
+    assert_eq!(
+        order.encode_data(),
+        vec![
+            Uint8(1).encode_word(),
+            1_700_000_000u32.encode_word(),
+            (-5i64).encode_word()
+        ]
+    );
+}
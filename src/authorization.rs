@@ -0,0 +1,312 @@
+//! EIP-3009 transfers with authorization, as implemented by USDC
+use crate::deadline::Clock;
+use crate::eip712::{EIP712Domain, FieldType, MessageTypes};
+use crate::error::{ErrorKind, Result};
+use crate::signing::{recover, sign, Signature};
+use crate::typed::{Eip712Struct, Eip712Type, StructuredData};
+use ethereum_types::{Address, H256, U256};
+use secp256k1::SecretKey;
+use std::time::Duration;
+
+/// a random `bytes32` nonce, EIP-3009 tokens track used nonces instead of a counter
+pub fn random_nonce() -> H256 {
+    H256(rand::random())
+}
+
+/// An authorization to move `value` from `from` to `to` while `valid_after < now < valid_before`
+pub trait TransferAuthorization: Eip712Struct {
+    /// the address that has to sign the authorization
+    fn from(&self) -> Address;
+
+    fn valid_after(&self) -> U256;
+
+    fn valid_before(&self) -> U256;
+
+    /// fails unless `now` lies strictly inside the validity window, like the
+    /// token does, tolerating `now` up to `skew` past `valid_before`
+    fn check_validity(&self, now: u64, skew: Duration) -> Result<()> {
+        let now = U256::from(now);
+        if now <= self.valid_after() {
+            Err(ErrorKind::NotYetValid(
+                "validAfter".into(),
+                saturating_u64(self.valid_after()),
+            ))?;
+        }
+        // a validBefore of type(uint256).max saturates and never expires
+        if now
+            >= self
+                .valid_before()
+                .saturating_add(U256::from(skew.as_secs()))
+        {
+            Err(ErrorKind::DeadlineExpired(
+                "validBefore".into(),
+                saturating_u64(self.valid_before()),
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+/// `value` for the `u64` timestamps of errors, `u64::MAX` if it doesn't fit
+fn saturating_u64(value: U256) -> u64 {
+    if value > U256::from(u64::MAX) {
+        u64::MAX
+    } else {
+        value.low_u64()
+    }
+}
+
+macro_rules! authorization {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $name {
+            pub from: Address,
+            pub to: Address,
+            pub value: U256,
+            /// unix seconds, exclusive
+            pub valid_after: U256,
+            /// unix seconds, exclusive
+            pub valid_before: U256,
+            pub nonce: H256,
+        }
+
+        impl $name {
+            /// an authorization with a random nonce
+            pub fn new(
+                from: Address,
+                to: Address,
+                value: U256,
+                valid_after: U256,
+                valid_before: U256,
+            ) -> Self {
+                $name {
+                    from,
+                    to,
+                    value,
+                    valid_after,
+                    valid_before,
+                    nonce: random_nonce(),
+                }
+            }
+        }
+
+        impl Eip712Struct for $name {
+            fn type_name() -> String {
+                stringify!($name).into()
+            }
+
+            fn fields() -> Vec<FieldType> {
+                vec![
+                    FieldType::new("from", "address"),
+                    FieldType::new("to", "address"),
+                    FieldType::new("value", "uint256"),
+                    FieldType::new("validAfter", "uint256"),
+                    FieldType::new("validBefore", "uint256"),
+                    FieldType::new("nonce", "bytes32"),
+                ]
+            }
+
+            fn add_dependencies(_types: &mut MessageTypes) {}
+
+            fn encode_data(&self) -> Vec<H256> {
+                vec![
+                    self.from.encode_word(),
+                    self.to.encode_word(),
+                    self.value.encode_word(),
+                    self.valid_after.encode_word(),
+                    self.valid_before.encode_word(),
+                    self.nonce.encode_word(),
+                ]
+            }
+        }
+
+        impl TransferAuthorization for $name {
+            fn from(&self) -> Address {
+                self.from
+            }
+
+            fn valid_after(&self) -> U256 {
+                self.valid_after
+            }
+
+            fn valid_before(&self) -> U256 {
+                self.valid_before
+            }
+        }
+    };
+}
+
+authorization!(
+    /// `TransferWithAuthorization(address from,address to,uint256 value,uint256 validAfter,uint256 validBefore,bytes32 nonce)`,
+    /// anyone holding the signature may submit it
+    TransferWithAuthorization
+);
+
+authorization!(
+    /// `ReceiveWithAuthorization(address from,address to,uint256 value,uint256 validAfter,uint256 validBefore,bytes32 nonce)`,
+    /// only `to` may submit it, which prevents front-running
+    ReceiveWithAuthorization
+);
+
+/// `CancelAuthorization(address authorizer,bytes32 nonce)`, revokes an unused authorization
+#[derive(Debug, Clone, PartialEq)]
+pub struct CancelAuthorization {
+    pub authorizer: Address,
+    pub nonce: H256,
+}
+
+impl Eip712Struct for CancelAuthorization {
+    fn type_name() -> String {
+        "CancelAuthorization".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("authorizer", "address"),
+            FieldType::new("nonce", "bytes32"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![self.authorizer.encode_word(), self.nonce.encode_word()]
+    }
+}
+
+/// Signs `message` for the token at `domain`
+pub fn sign_authorization<T: Eip712Struct>(
+    domain: &EIP712Domain,
+    message: &T,
+    secret_key: &SecretKey,
+) -> Result<Signature> {
+    Ok(sign(&(domain, message).digest()?, secret_key))
+}
+
+/// Checks that `authorization` is valid at `clock.now()` and was signed by its
+/// `from` address
+pub fn verify_authorization<A: TransferAuthorization, C: Clock>(
+    domain: &EIP712Domain,
+    authorization: &A,
+    signature: &Signature,
+    clock: &C,
+) -> Result<()> {
+    authorization.check_validity(clock.now(), clock.skew())?;
+    let signer = recover(&(domain, authorization).digest()?, signature)?;
+    if signer != authorization.from() {
+        Err(ErrorKind::UnauthorizedSigner(format!("0x{:x}", signer)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deadline::FakeClock;
+    use crate::signing::{address, test_key};
+
+    fn usdc() -> EIP712Domain {
+        EIP712Domain::from_parts(
+            "USD Coin",
+            "2",
+            1u64,
+            "A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48".parse().unwrap(),
+        )
+    }
+
+    fn transfer() -> TransferWithAuthorization {
+        TransferWithAuthorization::new(
+            address(&test_key()),
+            Address::from(&[0x22; 20][..]),
+            1_000_000.into(),
+            1_000.into(),
+            2_000.into(),
+        )
+    }
+
+    #[test]
+    fn it_uses_the_eip3009_type_hashes() {
+        // the constants from the EIP-3009 reference implementation
+        assert_eq!(
+            format!("{:x}", TransferWithAuthorization::type_hash()),
+            "7c7c6cdb67a18743f49ec6fa9b35f50d52ed05cbed4cc592e13b44501c1a2267"
+        );
+        assert_eq!(
+            format!("{:x}", ReceiveWithAuthorization::type_hash()),
+            "d099cc98ef71107a616c4f0f941f04c322d8e254fe26b3c6668db87aae413de8"
+        );
+        assert_eq!(
+            format!("{:x}", CancelAuthorization::type_hash()),
+            "158b0a9edf7a828aad02f63cd515c68ef2f50ba807396f6d12842833a1597429"
+        );
+    }
+
+    #[test]
+    fn it_generates_random_nonces() {
+        assert_ne!(transfer().nonce, transfer().nonce);
+        assert_ne!(random_nonce(), H256::zero());
+    }
+
+    #[test]
+    fn it_checks_the_validity_window() {
+        let transfer = transfer();
+        let signature = sign_authorization(&usdc(), &transfer, &test_key()).unwrap();
+
+        assert_eq!(
+            verify_authorization(&usdc(), &transfer, &signature, &FakeClock::new(1_000))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotYetValid("validAfter".into(), 1_000)
+        );
+        verify_authorization(&usdc(), &transfer, &signature, &FakeClock::new(1_001)).unwrap();
+        verify_authorization(&usdc(), &transfer, &signature, &FakeClock::new(1_999)).unwrap();
+        assert_eq!(
+            verify_authorization(&usdc(), &transfer, &signature, &FakeClock::new(2_000))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::DeadlineExpired("validBefore".into(), 2_000)
+        );
+    }
+
+    #[test]
+    fn it_never_expires_max_valid_before() {
+        let mut transfer = transfer();
+        transfer.valid_before = U256::max_value();
+
+        transfer
+            .check_validity(u64::MAX, Duration::from_secs(60))
+            .unwrap();
+
+        transfer.valid_after = U256::max_value();
+        assert_eq!(
+            transfer
+                .check_validity(u64::MAX, Duration::from_secs(0))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotYetValid("validAfter".into(), u64::MAX)
+        );
+    }
+
+    #[test]
+    fn it_separates_transfers_from_receives() {
+        let transfer = transfer();
+        let receive = ReceiveWithAuthorization {
+            from: transfer.from,
+            to: transfer.to,
+            value: transfer.value,
+            valid_after: transfer.valid_after,
+            valid_before: transfer.valid_before,
+            nonce: transfer.nonce,
+        };
+        let signature = sign_authorization(&usdc(), &transfer, &test_key()).unwrap();
+        let clock = FakeClock::new(1_500);
+
+        verify_authorization(&usdc(), &transfer, &signature, &clock).unwrap();
+        assert!(verify_authorization(&usdc(), &receive, &signature, &clock).is_err());
+
+        let mut other_nonce = transfer.clone();
+        other_nonce.nonce = random_nonce();
+        assert!(verify_authorization(&usdc(), &other_nonce, &signature, &clock).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+//! Batch hashing and signing of messages sharing one schema and domain
+use crate::eip712::{EIP712Domain, FieldType, EIP712};
+use crate::encode::{hash_domain, hash_struct, hash_typed_message};
+use crate::error::Result;
+use crate::parser::Parser;
+use crate::signing::sign_hash;
+use ethereum_types::H256;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use secp256k1::SecretKey;
+use serde_json::Value;
+use validator::Validate;
+
+/// digest and signature of a single message in a batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedMessage {
+    pub digest: H256,
+    pub signature: String,
+}
+
+/// hashes every message as an instance of `custom_field` under the given domain.
+///
+/// The schema and domain separator are validated and encoded once; the outer
+/// error is returned if that fails. Messages are then hashed independently
+/// (in parallel with the `rayon` feature) and the results keep input order.
+pub fn hash_batch<I>(
+    domain: &EIP712Domain,
+    custom_field: &(String, Vec<FieldType>),
+    messages: I,
+) -> Result<Vec<Result<H256>>>
+where
+    I: IntoIterator<Item = Value>,
+{
+    let template = EIP712::new(domain.clone(), Value::Null, Some(custom_field.clone()));
+    template.validate()?;
+    let domain_hash = hash_domain(&Parser::new(), &template.domain, &template.types)?;
+
+    let messages = messages.into_iter().collect::<Vec<_>>();
+    Ok(map_messages(&messages, |parser, message| {
+        let data_hash = hash_struct(parser, &template.primary_type, &template.types, message)?;
+        Ok(hash_typed_message(&domain_hash, &data_hash))
+    }))
+}
+
+/// like `hash_batch`, additionally signing every digest with `secret_key`
+pub fn sign_batch<I>(
+    domain: &EIP712Domain,
+    custom_field: &(String, Vec<FieldType>),
+    messages: I,
+    secret_key: &SecretKey,
+) -> Result<Vec<Result<SignedMessage>>>
+where
+    I: IntoIterator<Item = Value>,
+{
+    let digests = hash_batch(domain, custom_field, messages)?;
+    Ok(map_results(digests, |digest| SignedMessage {
+        signature: sign_hash(&digest, secret_key),
+        digest,
+    }))
+}
+
+#[cfg(feature = "rayon")]
+fn map_messages<T, F>(messages: &[Value], f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(&Parser, &Value) -> T + Sync + Send,
+{
+    messages
+        .par_iter()
+        .map_init(Parser::new, |parser, message| f(parser, message))
+        .collect()
+}
+
+#[cfg(not(feature = "rayon"))]
+fn map_messages<T, F>(messages: &[Value], f: F) -> Vec<T>
+where
+    F: Fn(&Parser, &Value) -> T,
+{
+    let parser = Parser::new();
+    messages.iter().map(|message| f(&parser, message)).collect()
+}
+
+#[cfg(feature = "rayon")]
+fn map_results<T, U, F>(items: Vec<Result<T>>, f: F) -> Vec<Result<U>>
+where
+    T: Send,
+    U: Send,
+    F: Fn(T) -> U + Sync + Send,
+{
+    items.into_par_iter().map(|item| item.map(&f)).collect()
+}
+
+#[cfg(not(feature = "rayon"))]
+fn map_results<T, U, F>(items: Vec<Result<T>>, f: F) -> Vec<Result<U>>
+where
+    F: Fn(T) -> U,
+{
+    items.into_iter().map(|item| item.map(&f)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::hash_structured_data;
+    use crate::error::ErrorKind;
+    use rustc_hex::ToHex;
+    use serde_json::json;
+
+    fn nft_data() -> (String, Vec<FieldType>) {
+        let fields = [
+            ("tokenId", "uint256"),
+            ("amount", "uint256"),
+            ("to", "address"),
+            ("nonce", "uint256"),
+        ];
+        let fields = fields
+            .iter()
+            .map(|(name, type_)| FieldType {
+                name: name.to_string(),
+                type_: type_.to_string(),
+            })
+            .collect();
+        ("NFTData".to_string(), fields)
+    }
+
+    fn domain() -> EIP712Domain {
+        EIP712Domain::new(
+            "AionRisingNFTs",
+            "0.0.1",
+            "0x7A69",
+            "0x037eDa3aDB1198021A9b2e88C22B464fD38db3f3",
+        )
+    }
+
+    fn message(nonce: u64) -> Value {
+        json!({
+            "tokenId": "0x1",
+            "amount": "0x1",
+            "to": "0x7FA9385bE102ac3EAc297483Dd6233D62b3e1496",
+            "nonce": format!("0x{:x}", nonce)
+        })
+    }
+
+    #[test]
+    fn it_hashes_batch_in_input_order() {
+        let messages = (1..=16).map(message).collect::<Vec<_>>();
+        let digests = hash_batch(&domain(), &nft_data(), messages.clone()).unwrap();
+
+        assert_eq!(digests.len(), messages.len());
+        for (digest, message) in digests.into_iter().zip(messages) {
+            let expected = hash_structured_data(EIP712::new(domain(), message, Some(nft_data())));
+            assert_eq!(digest.unwrap(), expected.unwrap());
+        }
+    }
+
+    #[test]
+    fn it_reports_errors_per_item() {
+        let mut invalid = message(2);
+        invalid["to"] = json!("0x1234");
+        let digests = hash_batch(
+            &domain(),
+            &nft_data(),
+            vec![message(1), invalid, message(3)],
+        )
+        .unwrap();
+
+        assert!(digests[0].is_ok());
+        assert_eq!(
+            digests[1].as_ref().unwrap_err().kind(),
+            ErrorKind::InvalidAddressLength(6)
+        );
+        assert!(digests[2].is_ok());
+    }
+
+    #[test]
+    fn it_fails_the_batch_on_invalid_schema() {
+        let (name, mut fields) = nft_data();
+        fields[0].name = "token id".to_string();
+        let result = hash_batch(&domain(), &(name, fields), vec![message(1)]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_signs_batch() {
+        let mut private_key = [0u8; 32];
+        private_key[28..].copy_from_slice(&659918_u32.to_be_bytes());
+        let secret_key = SecretKey::from_slice(&private_key).unwrap();
+
+        let signed = sign_batch(&domain(), &nft_data(), vec![message(1)], &secret_key).unwrap();
+        let signed = signed[0].as_ref().unwrap();
+
+        assert_eq!(
+            signed.digest.to_hex::<String>(),
+            "77915d20c811f39572463a234db9b776d518d07d9682a825be0d79752745a4c7"
+        );
+        assert_eq!(
+            signed.signature,
+            "b9c658f86d985ad0502584c70ea520cf68523e4013786f83f216de093ef9467e453d27fe627278ab0c8425906843a706f66a9c3120b37e88ac722aa217a04fcf1b"
+        );
+    }
+}
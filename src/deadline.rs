@@ -0,0 +1,245 @@
+//! Deadlines and expiry checks against an injectable clock
+use crate::error::{serde_error, ErrorKind, Result};
+use crate::value::Eip712Value;
+use ethereum_types::U256;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A source of the current time in unix seconds
+pub trait Clock {
+    fn now(&self) -> u64;
+
+    /// how long past their deadline messages are still accepted, to tolerate
+    /// clocks that run ahead of the chain's
+    fn skew(&self) -> Duration {
+        Duration::from_secs(0)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> u64 {
+        (**self).now()
+    }
+
+    fn skew(&self) -> Duration {
+        (**self).skew()
+    }
+}
+
+/// A clock whose deadlines are checked with a `skew`, e.g.
+/// `WithSkew::new(SystemClock, Duration::from_secs(30))`
+#[derive(Debug, Clone, Copy)]
+pub struct WithSkew<C> {
+    clock: C,
+    skew: Duration,
+}
+
+impl<C: Clock> WithSkew<C> {
+    pub fn new(clock: C, skew: Duration) -> Self {
+        WithSkew { clock, skew }
+    }
+}
+
+impl<C: Clock> Clock for WithSkew<C> {
+    fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    fn skew(&self) -> Duration {
+        self.skew
+    }
+}
+
+/// The system's wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// A clock that only moves when told to, for tests
+#[derive(Debug, Default)]
+pub struct FakeClock(AtomicU64);
+
+impl FakeClock {
+    pub fn new(now: u64) -> Self {
+        FakeClock(AtomicU64::new(now))
+    }
+
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.0.fetch_add(duration.as_secs(), Ordering::SeqCst);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// a deadline `duration` from now, e.g. for a `deadline` or `validUntil` field
+pub fn deadline_in<C: Clock>(clock: &C, duration: Duration) -> U256 {
+    U256::from(clock.now()) + U256::from(duration.as_secs())
+}
+
+/// Rejects messages whose timestamp field lies in the past
+#[derive(Debug, Clone)]
+pub struct DeadlineCheck {
+    field: String,
+    skew: Duration,
+}
+
+impl DeadlineCheck {
+    /// checks the field at `field`, nested struct fields are separated by dots,
+    /// e.g. `details.expiration`
+    pub fn new(field: &str) -> Self {
+        DeadlineCheck {
+            field: field.to_owned(),
+            skew: Duration::from_secs(0),
+        }
+    }
+
+    /// accepts messages up to `skew` after their deadline, to tolerate clocks
+    /// that run ahead of the chain's; the clock's own `skew` is used if it's larger
+    pub fn with_skew(mut self, skew: Duration) -> Self {
+        self.skew = skew;
+        self
+    }
+
+    /// reads the deadline from `message` and fails if it passed, returning it otherwise
+    pub fn check<C: Clock>(&self, message: &Eip712Value, clock: &C) -> Result<U256> {
+        let mut value = message;
+        for name in self.field.split('.') {
+            value = match value {
+                Eip712Value::Struct(type_name, fields) => fields
+                    .get(name)
+                    .ok_or_else(|| ErrorKind::MissingField(name.to_owned(), type_name.clone()))?,
+                _ => Err(serde_error("struct", Some(&self.field)))?,
+            };
+        }
+        let deadline = match value {
+            Eip712Value::Uint(_, deadline) => *deadline,
+            _ => Err(serde_error("uint", Some(&self.field)))?,
+        };
+        check_deadline(
+            &self.field,
+            deadline,
+            clock.now(),
+            self.skew.max(clock.skew()),
+        )?;
+        Ok(deadline)
+    }
+}
+
+/// fails if `now` is more than `skew` past `deadline`, deadlines are inclusive
+pub(crate) fn check_deadline(field: &str, deadline: U256, now: u64, skew: Duration) -> Result<()> {
+    // deadlines beyond u64, like type(uint256).max, never expire
+    if deadline > U256::from(u64::MAX) {
+        return Ok(());
+    }
+    let deadline = deadline.low_u64();
+    if now > deadline.saturating_add(skew.as_secs()) {
+        Err(ErrorKind::DeadlineExpired(field.to_owned(), deadline))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permit(deadline: U256) -> Eip712Value {
+        Eip712Value::new_struct(
+            "Permit",
+            vec![
+                ("value", Eip712Value::from(U256::from(1))),
+                (
+                    "details",
+                    Eip712Value::new_struct("Details", vec![("deadline", deadline.into())]),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn it_rejects_expired_deadlines() {
+        let clock = FakeClock::new(1_000);
+        let message = permit(deadline_in(&clock, Duration::from_secs(60)));
+        let check = DeadlineCheck::new("details.deadline");
+
+        assert_eq!(check.check(&message, &clock).unwrap(), 1_060.into());
+        clock.advance(Duration::from_secs(60));
+        assert!(check.check(&message, &clock).is_ok());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            check.check(&message, &clock).unwrap_err().kind(),
+            ErrorKind::DeadlineExpired("details.deadline".into(), 1_060)
+        );
+    }
+
+    #[test]
+    fn it_tolerates_clock_skew() {
+        let clock = FakeClock::new(1_065);
+        let message = permit(1_060.into());
+
+        assert!(DeadlineCheck::new("details.deadline")
+            .with_skew(Duration::from_secs(5))
+            .check(&message, &clock)
+            .is_ok());
+        clock.set(1_066);
+        assert!(DeadlineCheck::new("details.deadline")
+            .with_skew(Duration::from_secs(5))
+            .check(&message, &clock)
+            .is_err());
+
+        // or the clock's, whichever is larger
+        let skewed = WithSkew::new(&clock, Duration::from_secs(6));
+        assert_eq!(skewed.now(), 1_066);
+        assert!(DeadlineCheck::new("details.deadline")
+            .check(&message, &skewed)
+            .is_ok());
+        assert!(DeadlineCheck::new("details.deadline")
+            .with_skew(Duration::from_secs(5))
+            .check(&message, &skewed)
+            .is_ok());
+    }
+
+    #[test]
+    fn it_never_expires_max_deadlines() {
+        let clock = FakeClock::new(u64::MAX);
+
+        assert!(DeadlineCheck::new("details.deadline")
+            .check(&permit(U256::max_value()), &clock)
+            .is_ok());
+    }
+
+    #[test]
+    fn it_rejects_missing_and_non_integer_fields() {
+        let clock = SystemClock;
+        let message = permit(0.into());
+
+        assert_eq!(
+            DeadlineCheck::new("details.validUntil")
+                .check(&message, &clock)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::MissingField("validUntil".into(), "Details".into())
+        );
+        assert!(DeadlineCheck::new("details")
+            .check(&message, &clock)
+            .is_err());
+        assert!(DeadlineCheck::new("value.deadline")
+            .check(&message, &clock)
+            .is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! EIP-191 signed data: `0x19 <version> <version specific data> <data>`
+use crate::error::Result;
+use crate::signing::{recover, sign, Signature};
+use crate::typed::StructuredData;
+use ethereum_types::{Address, H256};
+use keccak_hash::keccak;
+use secp256k1::SecretKey;
+
+/// Data signed under one of the three EIP-191 versions
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignedData {
+    /// `0x00`, data only `validator` should accept, e.g. a multisig
+    WithValidator { validator: Address, data: Vec<u8> },
+    /// `0x01`, an EIP-712 message
+    Structured {
+        domain_separator: H256,
+        struct_hash: H256,
+    },
+    /// `0x45`, `personal_sign`'s `Ethereum Signed Message:\n<len>` prefix
+    PersonalMessage(Vec<u8>),
+}
+
+impl SignedData {
+    /// the EIP-712 form of `data`, e.g. a domain paired with an `Eip712Struct`
+    pub fn structured<D: StructuredData>(data: &D) -> Result<Self> {
+        Ok(SignedData::Structured {
+            domain_separator: data.domain_separator()?,
+            struct_hash: data.message_hash()?,
+        })
+    }
+
+    /// the version byte following `0x19`
+    pub fn version(&self) -> u8 {
+        match self {
+            SignedData::WithValidator { .. } => 0x00,
+            SignedData::Structured { .. } => 0x01,
+            SignedData::PersonalMessage(_) => 0x45,
+        }
+    }
+
+    /// the bytes that get hashed
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = vec![0x19, self.version()];
+        match self {
+            SignedData::WithValidator { validator, data } => {
+                encoded.extend_from_slice(&validator.0);
+                encoded.extend_from_slice(data);
+            }
+            SignedData::Structured {
+                domain_separator,
+                struct_hash,
+            } => {
+                encoded.extend_from_slice(&domain_separator.0);
+                encoded.extend_from_slice(&struct_hash.0);
+            }
+            SignedData::PersonalMessage(message) => {
+                // the version byte is the "E" of "Ethereum"
+                encoded.extend_from_slice(b"thereum Signed Message:\n");
+                encoded.extend_from_slice(message.len().to_string().as_bytes());
+                encoded.extend_from_slice(message);
+            }
+        }
+        encoded
+    }
+
+    /// the digest to sign
+    pub fn hash(&self) -> H256 {
+        keccak(self.encode())
+    }
+
+    pub fn sign(&self, secret_key: &SecretKey) -> Signature {
+        sign(&self.hash(), secret_key)
+    }
+
+    /// the address that signed the data, see `signing::recover`
+    pub fn recover(&self, signature: &Signature) -> Result<Address> {
+        recover(&self.hash(), signature)
+    }
+}
+
+/// the `personal_sign` digest of `message`, like ethers' `hashMessage` and
+/// OpenZeppelin's `toEthSignedMessageHash`
+pub fn personal_message_hash<M: AsRef<[u8]>>(message: M) -> H256 {
+    SignedData::PersonalMessage(message.as_ref().to_vec()).hash()
+}
+
+/// signs `message` the way wallets do for `personal_sign`
+pub fn sign_personal_message<M: AsRef<[u8]>>(message: M, secret_key: &SecretKey) -> Signature {
+    sign(&personal_message_hash(message), secret_key)
+}
+
+/// the address that signed `message` with `personal_sign`
+pub fn recover_personal_message<M: AsRef<[u8]>>(
+    message: M,
+    signature: &Signature,
+) -> Result<Address> {
+    recover(&personal_message_hash(message), signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eip712::EIP712Domain;
+    use crate::signing::{address, test_key};
+    use crate::voucher::NftData;
+
+    #[test]
+    fn it_hashes_personal_messages() {
+        // ethers.utils.hashMessage("hello world")
+        assert_eq!(
+            format!("{:x}", personal_message_hash("hello world")),
+            "d9eba16ed0ecae432b71fe008c98cc872bb4cc214d3220a36f365326cf807d68"
+        );
+        assert_eq!(
+            SignedData::PersonalMessage(b"hi".to_vec()).encode(),
+            b"\x19Ethereum Signed Message:\n2hi".to_vec()
+        );
+
+        let signature = sign_personal_message("Sign in to example.com", &test_key());
+        assert_eq!(
+            recover_personal_message("Sign in to example.com", &signature).unwrap(),
+            address(&test_key())
+        );
+        assert_ne!(
+            recover_personal_message("Sign in to evil.com", &signature).unwrap(),
+            address(&test_key())
+        );
+    }
+
+    #[test]
+    fn it_binds_data_to_its_validator() {
+        let validator = Address::from(&[0xaa; 20][..]);
+        let data = SignedData::WithValidator {
+            validator,
+            data: vec![0xde, 0xad],
+        };
+        assert_eq!(
+            data.encode(),
+            [&[0x19, 0x00][..], &[0xaa; 20], &[0xde, 0xad]].concat()
+        );
+
+        let signature = data.sign(&test_key());
+        assert_eq!(data.recover(&signature).unwrap(), address(&test_key()));
+        let other = SignedData::WithValidator {
+            validator: Address::from(&[0xbb; 20][..]),
+            data: vec![0xde, 0xad],
+        };
+        assert_ne!(other.recover(&signature).unwrap(), address(&test_key()));
+    }
+
+    #[test]
+    fn it_matches_the_eip712_digest() {
+        let domain = EIP712Domain::from_parts(
+            "AionRisingNFTs",
+            "0.0.1",
+            31337u64,
+            "037eDa3aDB1198021A9b2e88C22B464fD38db3f3".parse().unwrap(),
+        );
+        let message = NftData {
+            token_id: 1.into(),
+            amount: 1.into(),
+            to: "7FA9385bE102ac3EAc297483Dd6233D62b3e1496".parse().unwrap(),
+            nonce: 1.into(),
+        };
+        let data = SignedData::structured(&(&domain, &message)).unwrap();
+
+        assert_eq!(data.version(), 0x01);
+        assert_eq!(data.hash(), (&domain, &message).digest().unwrap());
+    }
+}
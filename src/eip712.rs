@@ -4,25 +4,29 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::str::FromStr;
 use validator::Validate;
 use validator::ValidationErrors;
 
 use once_cell::sync::Lazy;
 
-use crate::create_domain;
+use crate::error;
+use crate::value::Eip712Value;
 
 pub type MessageTypes = HashMap<String, Vec<FieldType>>;
 
 static TYPE_REGEX: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^[a-zA-Z_$][a-zA-Z_$0-9]*(\[([1-9]\d*)*\])*$").unwrap());
-static IDENT_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z_$][a-zA-Z_$0-9]*$").unwrap());
+pub(crate) static IDENT_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[a-zA-Z_$][a-zA-Z_$0-9]*$").unwrap());
 
-#[derive(Deserialize, Serialize, Validate, Debug, Clone)]
+#[derive(Deserialize, Serialize, Validate, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct EIP712Domain {
     pub(crate) name: String,
-    pub(crate) version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) version: Option<String>,
     pub(crate) chain_id: U256,
     pub(crate) verifying_contract: Address,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -30,16 +34,104 @@ pub struct EIP712Domain {
 }
 
 impl EIP712Domain {
+    /// parses `chain_id` and `verifying_contract` from 0x-prefixed hex, `name`
+    /// and `version` are taken as they are
+    ///
+    /// # Panics
+    ///
+    /// if `chain_id` or `verifying_contract` isn't valid hex
     pub fn new(name: &str, version: &str, chain_id: &str, verifying_contract: &str) -> Self {
-        let json_data = create_domain(name, version, chain_id, verifying_contract);
-        let domain: EIP712Domain = match serde_json::from_str(&json_data) {
-            Ok(domain) => domain,
-            Err(e) => {
-                println!("Error parsing EIP712Domain {:?}", e);
-                panic!()
-            }
-        };
-        domain
+        let chain_id = chain_id
+            .strip_prefix("0x")
+            .and_then(|hex| U256::from_str(hex).ok())
+            .unwrap_or_else(|| panic!("Error parsing EIP712Domain chainId {:?}", chain_id));
+        let verifying_contract = verifying_contract
+            .strip_prefix("0x")
+            .and_then(|hex| Address::from_str(hex).ok())
+            .unwrap_or_else(|| {
+                panic!(
+                    "Error parsing EIP712Domain verifyingContract {:?}",
+                    verifying_contract
+                )
+            });
+        EIP712Domain::from_parts(name, version, chain_id, verifying_contract)
+    }
+
+    /// builds the domain from typed values, strings are never interpreted
+    pub fn from_parts<C: Into<U256>>(
+        name: &str,
+        version: &str,
+        chain_id: C,
+        verifying_contract: Address,
+    ) -> Self {
+        EIP712Domain {
+            name: name.to_owned(),
+            version: Some(version.to_owned()),
+            chain_id: chain_id.into(),
+            verifying_contract,
+            salt: None,
+        }
+    }
+
+    /// a domain without a `version` field, as used by e.g. Permit2
+    pub fn unversioned<C: Into<U256>>(
+        name: &str,
+        chain_id: C,
+        verifying_contract: Address,
+    ) -> Self {
+        EIP712Domain {
+            name: name.to_owned(),
+            version: None,
+            chain_id: chain_id.into(),
+            verifying_contract,
+            salt: None,
+        }
+    }
+
+    pub fn with_salt(mut self, salt: H256) -> Self {
+        self.salt = Some(salt);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub fn chain_id(&self) -> U256 {
+        self.chain_id
+    }
+
+    pub fn verifying_contract(&self) -> Address {
+        self.verifying_contract
+    }
+
+    pub fn salt(&self) -> Option<H256> {
+        self.salt
+    }
+
+    /// the `EIP712Domain` type of this domain, `version` and `salt` are only
+    /// included if set
+    pub fn field_types(&self) -> Vec<FieldType> {
+        let mut fields = vec![("name", "string")];
+        if self.version.is_some() {
+            fields.push(("version", "string"));
+        }
+        fields.push(("chainId", "uint256"));
+        fields.push(("verifyingContract", "address"));
+        if self.salt.is_some() {
+            fields.push(("salt", "bytes32"));
+        }
+        fields
+            .into_iter()
+            .map(|(name, type_)| FieldType {
+                name: name.to_string(),
+                type_: type_.to_string(),
+            })
+            .collect()
     }
 }
 
@@ -67,6 +159,12 @@ impl EIP712Builder {
         self
     }
 
+    /// sets a domain built with `EIP712Domain::from_parts`
+    pub fn typed_domain(mut self, domain: EIP712Domain) -> Self {
+        self.domain = Some(domain);
+        self
+    }
+
     pub fn custom_field(mut self, field_types: (String, Vec<FieldType>)) -> Self {
         self.custom_field = Some(field_types);
         self
@@ -119,26 +217,7 @@ impl EIP712 {
         custom_field: Option<(String, Vec<FieldType>)>,
     ) -> Self {
         let mut types = HashMap::new();
-        let field_types_eip_domain = vec![
-            FieldType {
-                name: "name".to_string(),
-                type_: "string".to_string(),
-            },
-            FieldType {
-                name: "version".to_string(),
-                type_: "string".to_string(),
-            },
-            FieldType {
-                name: "chainId".to_string(),
-                type_: "uint256".to_string(),
-            },
-            FieldType {
-                name: "verifyingContract".to_string(),
-                type_: "address".to_string(),
-            },
-        ];
-
-        types.insert("EIP712Domain".to_string(), field_types_eip_domain);
+        types.insert("EIP712Domain".to_string(), domain.field_types());
 
         let mut primary_type = "EI712Domain".to_string();
         if let Some((type_name, fields)) = custom_field {
@@ -156,9 +235,13 @@ impl EIP712 {
     pub fn add_type(&mut self, type_name: String, fields: Vec<FieldType>) {
         self.types.insert(type_name, fields);
     }
+    /// the message as a typed value, checked against the declared types
+    pub fn typed_message(&self) -> error::Result<Eip712Value> {
+        Eip712Value::from_json(&self.message, &self.primary_type, &self.types)
+    }
 }
 
-#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+#[derive(Serialize, Deserialize, Validate, Debug, Clone, PartialEq)]
 pub struct FieldType {
     #[validate(regex(path = *IDENT_REGEX))]
     pub name: String,
@@ -166,3 +249,108 @@ pub struct FieldType {
     #[validate(regex(path = *TYPE_REGEX))]
     pub type_: String,
 }
+
+impl FieldType {
+    pub(crate) fn new(name: &str, type_: &str) -> Self {
+        FieldType {
+            name: name.to_owned(),
+            type_: type_.to_owned(),
+        }
+    }
+}
+
+/// `value` as a `0x`-prefixed hex string, the way JSON messages carry integers and addresses
+pub(crate) fn hex<T: std::fmt::LowerHex>(value: T) -> String {
+    format!("0x{:x}", value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::hash_domain;
+    use crate::nft_helpers::{create_domain, generate_typed_data, MessageSchema};
+    use crate::parser::Parser;
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    proptest! {
+        #[test]
+        fn domains_round_trip_arbitrary_strings(
+            name in any::<String>(),
+            version in any::<String>(),
+            chain_id in any::<u64>(),
+            contract in any::<[u8; 20]>(),
+        ) {
+            let verifying_contract = Address::from(&contract[..]);
+            let domain = EIP712Domain::from_parts(&name, &version, chain_id, verifying_contract);
+            prop_assert_eq!(domain.name(), name.as_str());
+            prop_assert_eq!(domain.version(), Some(version.as_str()));
+
+            let json = serde_json::to_string(&domain).unwrap();
+            prop_assert_eq!(&serde_json::from_str::<EIP712Domain>(&json).unwrap(), &domain);
+
+            let legacy = EIP712Domain::new(
+                &name,
+                &version,
+                &format!("0x{:x}", chain_id),
+                &format!("0x{:x}", verifying_contract),
+            );
+            prop_assert_eq!(&legacy, &domain);
+
+            let domain_json = create_domain(
+                &name,
+                &version,
+                &format!("0x{:x}", chain_id),
+                &format!("0x{:x}", verifying_contract),
+            );
+            let parsed = serde_json::from_str::<Value>(&domain_json).unwrap();
+            prop_assert_eq!(parsed.as_object().unwrap().len(), 4);
+            prop_assert_eq!(&serde_json::from_value::<EIP712Domain>(parsed).unwrap(), &domain);
+
+            // the JSON encoder agrees with the typed one, so no string leaks into the layout
+            let mut types = HashMap::new();
+            types.insert("EIP712Domain".to_string(), domain.field_types());
+            prop_assert_eq!(
+                hash_domain(&Parser::new(), &domain, &types).unwrap(),
+                domain.separator()
+            );
+        }
+
+        #[test]
+        fn typed_data_round_trips_arbitrary_strings(name in any::<String>(), title in any::<String>()) {
+            let domain = EIP712Domain::from_parts(&name, "1", 1u64, Address::zero());
+            let schema = MessageSchema::new(
+                "Quest",
+                vec![FieldType { name: "title".into(), type_: "string".into() }],
+            );
+            let typed_data = generate_typed_data(&domain, &schema, json!({ "title": title })).unwrap();
+
+            let parsed = serde_json::from_str::<EIP712>(&typed_data.to_string()).unwrap();
+            prop_assert_eq!(&parsed.domain, &domain);
+            prop_assert_eq!(&parsed.message["title"], &json!(title));
+        }
+    }
+
+    #[test]
+    fn it_includes_the_salt_in_the_domain_type() {
+        let salt = H256::from(&[0xab; 32][..]);
+        let domain = EIP712Domain::from_parts("Ether Mail", "1", 1u64, Address::zero());
+        assert_eq!(domain.field_types().len(), 4);
+
+        let domain = domain.with_salt(salt);
+        let data = EIP712::builder()
+            .typed_domain(domain.clone())
+            .custom_field(("Empty".into(), vec![]))
+            .message(json!({}))
+            .build();
+        assert_eq!(data.types["EIP712Domain"][4].name, "salt");
+        assert_eq!(domain.salt(), Some(salt));
+        let mut types = HashMap::new();
+        types.insert("EIP712Domain".to_string(), domain.field_types());
+        assert_eq!(
+            hash_domain(&Parser::new(), &domain, &types).unwrap(),
+            domain.separator()
+        );
+        assert!(crate::hash_structured_data(data).is_ok());
+    }
+}
@@ -1,28 +1,29 @@
 //! EIP712 Encoder
-use crate::eip712::{MessageTypes, EIP712};
+use crate::eip712::{EIP712Domain, FieldType, MessageTypes, EIP712, IDENT_REGEX};
 use crate::error::{serde_error, ErrorKind, Result};
 use crate::parser::{Parser, Type};
+use crate::typed::StructuredData;
+use crate::value::{fits, from_json, Eip712Value};
 use ethabi::{encode, Token as EthAbiToken};
-use ethereum_types::{Address as EthAddress, H256, U256};
+use ethereum_types::H256;
 use indexmap::IndexSet;
 use itertools::Itertools;
 use keccak_hash::keccak;
-use rustc_hex::FromHex;
 use serde_json::to_value;
 use serde_json::Value;
 use std::collections::HashSet;
-use std::str::FromStr;
 use validator::Validate;
 
-fn check_hex(string: &str) -> Result<()> {
+pub(crate) fn check_hex(string: &str) -> Result<()> {
     if string.len() >= 2 && &string[..2] == "0x" {
         return Ok(());
     }
 
-    return Err(ErrorKind::HexParseError(format!(
+    Err(ErrorKind::HexParseError(format!(
         "Expected a 0x-prefixed string of even length, found {} length string",
         string.len()
-    )))?;
+    ))
+    .into())
 }
 /// given a type and HashMap<String, Vec<FieldType>>
 /// returns a HashSet of dependent types of the given type
@@ -30,9 +31,7 @@ fn build_dependencies<'a>(
     message_type: &'a str,
     message_types: &'a MessageTypes,
 ) -> Option<HashSet<&'a str>> {
-    if message_types.get(message_type).is_none() {
-        return None;
-    }
+    message_types.get(message_type)?;
 
     let mut types = IndexSet::new();
     types.insert(message_type);
@@ -43,28 +42,37 @@ fn build_dependencies<'a>(
             deps.insert(item);
 
             for field in fields {
+                // arrays of structs depend on the struct type itself
+                let type_ = field.type_.split('[').next().unwrap_or(&field.type_);
                 // seen this type before? or not a custom type skip
-                if deps.contains(&*field.type_) || !message_types.contains_key(&*field.type_) {
+                if deps.contains(type_) || !message_types.contains_key(type_) {
                     continue;
                 }
-                types.insert(&*field.type_);
+                types.insert(type_);
             }
         }
     }
 
-    return Some(deps);
+    Some(deps)
 }
 
-fn encode_type(message_type: &str, message_types: &MessageTypes) -> Result<String> {
-    let deps = {
-        let mut temp = build_dependencies(message_type, message_types)
-            .ok_or_else(|| ErrorKind::NonExistentType)?;
-        temp.remove(message_type);
-        let mut temp = temp.into_iter().collect::<Vec<_>>();
-        (&mut temp[..]).sort_unstable();
-        temp.insert(0, message_type);
-        temp
-    };
+/// the given type followed by its dependencies sorted by name, as in `encodeType`
+pub(crate) fn sorted_dependencies<'a>(
+    message_type: &'a str,
+    message_types: &'a MessageTypes,
+) -> Result<Vec<&'a str>> {
+    let mut temp =
+        build_dependencies(message_type, message_types).ok_or(ErrorKind::NonExistentType)?;
+    temp.remove(message_type);
+    let mut temp = temp.into_iter().collect::<Vec<_>>();
+    temp[..].sort_unstable();
+    temp.insert(0, message_type);
+    Ok(temp)
+}
+
+/// `encodeType` of `message_type`: the type followed by its dependencies sorted by name
+pub fn encode_type(message_type: &str, message_types: &MessageTypes) -> Result<String> {
+    let deps = sorted_dependencies(message_type, message_types)?;
 
     let encoded = deps
         .into_iter()
@@ -74,7 +82,7 @@ fn encode_type(message_type: &str, message_types: &MessageTypes) -> Result<Strin
                     .iter()
                     .map(|value| format!("{} {}", value.type_, value.name))
                     .join(",");
-                return format!("{}({})", dep, types);
+                format!("{}({})", dep, types)
             })
         })
         .collect::<Vec<_>>()
@@ -82,29 +90,120 @@ fn encode_type(message_type: &str, message_types: &MessageTypes) -> Result<Strin
     Ok(encoded)
 }
 
-fn type_hash(message_type: &str, typed_data: &MessageTypes) -> Result<H256> {
+/// parses an `encodeType` string like `Mail(Person from,string contents)Person(string name)`
+/// into the primary type and its `MessageTypes`, the inverse of `encode_type`.
+/// fails unless the dependencies are exactly the ones referenced by the primary type,
+/// sorted by name.
+pub fn parse_encoded_type(encoded: &str) -> Result<(String, MessageTypes)> {
+    let invalid = |reason: String| ErrorKind::InvalidEncodedType(encoded.to_owned(), reason);
+    let parser = Parser::new();
+    let mut order = vec![];
+    let mut message_types = MessageTypes::new();
+    let mut rest = encoded;
+
+    while !rest.is_empty() {
+        let open = rest
+            .find('(')
+            .ok_or_else(|| invalid(format!("expected '(' after '{}'", rest)))?;
+        let close = rest
+            .find(')')
+            .ok_or_else(|| invalid(format!("missing ')' in '{}'", rest)))?;
+        if close < open {
+            Err(invalid(format!("unexpected ')' in '{}'", rest)))?;
+        }
+        let name = &rest[..open];
+        let members = &rest[open + 1..close];
+        rest = &rest[close + 1..];
+
+        let mut fields = vec![];
+        for member in members.split(',').filter(|_| !members.is_empty()) {
+            let (type_, field_name) = member
+                .split_once(' ')
+                .ok_or_else(|| invalid(format!("expected '<type> <name>', got '{}'", member)))?;
+            let field = FieldType {
+                name: field_name.to_owned(),
+                type_: type_.to_owned(),
+            };
+            field.validate()?;
+            // `encodeType` only ever contains canonical names, e.g. `uint256` rather than `uint`
+            if String::from(parser.parse_type(type_)?) != type_ {
+                Err(invalid(format!("'{}' isn't a canonical type name", type_)))?;
+            }
+            fields.push(field);
+        }
+
+        if !IDENT_REGEX.is_match(name) {
+            Err(invalid(format!("'{}' isn't a valid struct name", name)))?;
+        }
+        if message_types.insert(name.to_owned(), fields).is_some() {
+            Err(invalid(format!("'{}' is declared more than once", name)))?;
+        }
+        order.push(name);
+    }
+
+    let primary_type = *order
+        .first()
+        .ok_or_else(|| invalid("expected at least one struct".into()))?;
+    for (name, fields) in &message_types {
+        for field in fields {
+            if let Type::Custom(type_) = base_type(parser.parse_type(&field.type_)?) {
+                if !message_types.contains_key(&type_) {
+                    Err(ErrorKind::UnknownType(
+                        format!("{}.{}", name, field.name),
+                        type_,
+                    ))?;
+                }
+            }
+        }
+    }
+
+    let expected = sorted_dependencies(primary_type, &message_types)?;
+    if let Some(unused) = order.iter().find(|name| !expected.contains(name)) {
+        Err(invalid(format!(
+            "'{}' isn't a dependency of '{}'",
+            unused, primary_type
+        )))?;
+    }
+    if expected != order {
+        Err(invalid(format!(
+            "dependencies must be sorted by name, expected {}",
+            expected.join(", ")
+        )))?;
+    }
+
+    Ok((primary_type.to_owned(), message_types))
+}
+
+/// the element type of (nested) arrays
+fn base_type(type_: Type) -> Type {
+    match type_ {
+        Type::Array { inner, .. } => base_type(*inner),
+        type_ => type_,
+    }
+}
+
+pub(crate) fn type_hash(message_type: &str, typed_data: &MessageTypes) -> Result<H256> {
     Ok(keccak(encode_type(message_type, typed_data)?))
 }
 
-fn encode_data(
+pub(crate) fn encode_data(
     parser: &Parser,
     message_type: &Type,
     message_types: &MessageTypes,
-    value: &Value,
+    value: &Eip712Value,
     field_name: Option<&str>,
 ) -> Result<Vec<u8>> {
-    let encoded = match message_type {
-        Type::Array { inner, length } => {
+    let mismatch = || serde_error(&format!("{}", message_type), field_name);
+
+    let encoded = match (message_type, value) {
+        (Type::Array { inner, length }, Eip712Value::Array(values)) => {
             let mut items = vec![];
-            let values = value
-                .as_array()
-                .ok_or_else(|| serde_error("array", field_name))?;
 
             // check if the type definition actually matches
             // the length of items to be encoded
             if length.is_some() && Some(values.len() as u64) != *length {
                 let array_type = format!("{}[{}]", *inner, length.unwrap());
-                return Err(ErrorKind::UnequalArrayItems(
+                Err(ErrorKind::UnequalArrayItems(
                     length.unwrap(),
                     array_type,
                     values.len() as u64,
@@ -112,139 +211,243 @@ fn encode_data(
             }
 
             for item in values {
-                let mut encoded = encode_data(parser, &*inner, &message_types, item, field_name)?;
+                let mut encoded = encode_data(parser, inner, message_types, item, field_name)?;
                 items.append(&mut encoded);
             }
 
             keccak(items).to_vec()
         }
 
-        Type::Custom(ref ident) if message_types.get(&*ident).is_some() => {
-            let type_hash = (&type_hash(ident, &message_types)?).to_vec();
+        (Type::Custom(ref ident), Eip712Value::Struct(name, fields))
+            if message_types.get(ident).is_some() && ident == name =>
+        {
+            let type_hash = type_hash(ident, message_types)?.to_vec();
             let mut tokens = encode(&[EthAbiToken::FixedBytes(type_hash)]);
 
             for field in message_types
                 .get(ident)
                 .expect("Already checked in match guard; qed")
             {
-                let value = &value[&field.name];
-                let type_ = parser.parse_type(&*field.type_)?;
+                let type_ = parser.parse_type(&field.type_)?;
+                let value = fields
+                    .get(&field.name)
+                    .ok_or_else(|| serde_error(&field.type_, Some(&field.name)))?;
                 let mut encoded =
-                    encode_data(parser, &type_, &message_types, &value, Some(&*field.name))?;
+                    encode_data(parser, &type_, message_types, value, Some(&*field.name))?;
                 tokens.append(&mut encoded);
             }
 
             keccak(tokens).to_vec()
         }
 
-        Type::Bytes => {
-            let string = value
-                .as_str()
-                .ok_or_else(|| serde_error("string", field_name))?;
-
-            check_hex(&string)?;
-
-            let bytes = (&string[2..])
-                .from_hex::<Vec<u8>>()
-                .map_err(|err| ErrorKind::HexParseError(format!("{}", err)))?;
-            let bytes = keccak(&bytes).to_vec();
-
+        (Type::Bytes, Eip712Value::Bytes(bytes)) => {
+            let bytes = keccak(bytes).to_vec();
             encode(&[EthAbiToken::FixedBytes(bytes)])
         }
 
-        Type::Byte(_) => {
-            let string = value
-                .as_str()
-                .ok_or_else(|| serde_error("string", field_name))?;
-
-            check_hex(&string)?;
-
-            let bytes = (&string[2..])
-                .from_hex::<Vec<u8>>()
-                .map_err(|err| ErrorKind::HexParseError(format!("{}", err)))?;
-
-            encode(&[EthAbiToken::FixedBytes(bytes)])
+        (Type::Byte(len), Eip712Value::FixedBytes(bytes)) => {
+            if bytes.len() > *len as usize {
+                Err(mismatch())?;
+            }
+            encode(&[EthAbiToken::FixedBytes(bytes.clone())])
         }
 
-        Type::String => {
-            let value = value
-                .as_str()
-                .ok_or_else(|| serde_error("string", field_name))?;
+        (Type::String, Eip712Value::String(value)) => {
             let hash = keccak(value).to_vec();
             encode(&[EthAbiToken::FixedBytes(hash)])
         }
 
-        Type::Bool => encode(&[EthAbiToken::Bool(
-            value
-                .as_bool()
-                .ok_or_else(|| serde_error("bool", field_name))?,
-        )]),
-
-        Type::Address => {
-            let addr = value
-                .as_str()
-                .ok_or_else(|| serde_error("string", field_name))?;
-            if addr.len() != 42 {
-                return Err(ErrorKind::InvalidAddressLength(addr.len()))?;
-            }
-            let address = EthAddress::from_str(&addr[2..])
-                .map_err(|err| ErrorKind::HexParseError(format!("{}", err)))?;
-            encode(&[EthAbiToken::Address(address)])
-        }
+        (Type::Bool, Eip712Value::Bool(value)) => encode(&[EthAbiToken::Bool(*value)]),
 
-        Type::Uint | Type::Int => {
-            let string = value
-                .as_str()
-                .ok_or_else(|| serde_error("int/uint", field_name))?;
+        (Type::Address, Eip712Value::Address(address)) => encode(&[EthAbiToken::Address(*address)]),
 
-            check_hex(&string)?;
-
-            let uint = U256::from_str(&string[2..])
-                .map_err(|err| ErrorKind::HexParseError(format!("{}", err)))?;
+        (Type::Uint(bits), Eip712Value::Uint(_, uint)) => {
+            if !fits(uint, *bits, false) {
+                Err(ErrorKind::IntegerOutOfRange(
+                    field_name.unwrap_or("").to_string(),
+                    format!("{}", message_type),
+                ))?;
+            }
+            encode(&[EthAbiToken::Uint(*uint)])
+        }
 
-            let token = if *message_type == Type::Uint {
-                EthAbiToken::Uint(uint)
-            } else {
-                EthAbiToken::Int(uint)
-            };
-            encode(&[token])
+        (Type::Int(bits), Eip712Value::Int(_, int)) => {
+            if !fits(int, *bits, true) {
+                Err(ErrorKind::IntegerOutOfRange(
+                    field_name.unwrap_or("").to_string(),
+                    format!("{}", message_type),
+                ))?;
+            }
+            encode(&[EthAbiToken::Int(*int)])
         }
 
-        _ => {
+        (Type::Custom(ref ident), _) if message_types.get(ident).is_none() => {
             return Err(ErrorKind::UnknownType(
-                format!("{}", field_name.unwrap_or("")),
+                field_name.unwrap_or("").to_string(),
                 format!("{}", *message_type),
             ))?
         }
+
+        _ => return Err(mismatch())?,
     };
 
     Ok(encoded)
 }
 
-/// encodes and hashes the given EIP712 struct
-pub fn hash_structured_data(typed_data: EIP712) -> Result<H256> {
-    // validate input
-    typed_data.validate()?;
-    // EIP-191 compliant
+/// encodes and hashes the given EIP712Domain, i.e. computes the domain separator
+pub(crate) fn hash_domain(
+    parser: &Parser,
+    domain: &EIP712Domain,
+    message_types: &MessageTypes,
+) -> Result<H256> {
+    let domain = to_value(domain).unwrap();
+    hash_struct(parser, "EIP712Domain", message_types, &domain)
+}
+
+/// encodes and hashes the given message as an instance of `message_type`
+pub(crate) fn hash_struct(
+    parser: &Parser,
+    message_type: &str,
+    message_types: &MessageTypes,
+    message: &Value,
+) -> Result<H256> {
+    let message_type = Type::Custom(message_type.into());
+    let value = from_json(parser, &message_type, message_types, message, None)?;
+    hash_struct_value(parser, &message_type, message_types, &value)
+}
+
+/// encodes and hashes the given typed value as an instance of `message_type`
+pub(crate) fn hash_struct_value(
+    parser: &Parser,
+    message_type: &Type,
+    message_types: &MessageTypes,
+    value: &Eip712Value,
+) -> Result<H256> {
+    let encoded = encode_data(parser, message_type, message_types, value, None)?;
+    Ok(H256::from(&encoded[..]))
+}
+
+/// combines a domain separator and a struct hash into the EIP-191 compliant digest
+pub(crate) fn hash_typed_message(domain_hash: &H256, struct_hash: &H256) -> H256 {
     let prefix = (b"\x19\x01").to_vec();
-    let domain = to_value(&typed_data.domain).unwrap();
-    let parser = Parser::new();
-    let (domain_hash, data_hash) = (
-        encode_data(
-            &parser,
-            &Type::Custom("EIP712Domain".into()),
-            &typed_data.types,
-            &domain,
-            None,
-        )?,
-        encode_data(
-            &parser,
-            &Type::Custom(typed_data.primary_type),
-            &typed_data.types,
-            &typed_data.message,
-            None,
-        )?,
-    );
-    let concat = [&prefix[..], &domain_hash[..], &data_hash[..]].concat();
-    Ok(keccak(concat))
+    let concat = [&prefix[..], &domain_hash[..], &struct_hash[..]].concat();
+    keccak(concat)
+}
+
+impl StructuredData for EIP712 {
+    fn domain_separator(&self) -> Result<H256> {
+        self.validate()?;
+        hash_domain(&Parser::new(), &self.domain, &self.types)
+    }
+
+    fn message_hash(&self) -> Result<H256> {
+        self.validate()?;
+        hash_struct(
+            &Parser::new(),
+            &self.primary_type,
+            &self.types,
+            &self.message,
+        )
+    }
+
+    fn digest(&self) -> Result<H256> {
+        // validate input
+        self.validate()?;
+        let parser = Parser::new();
+        let domain_hash = hash_domain(&parser, &self.domain, &self.types)?;
+        let data_hash = hash_struct(&parser, &self.primary_type, &self.types, &self.message)?;
+        Ok(hash_typed_message(&domain_hash, &data_hash))
+    }
+}
+
+impl StructuredData for (&EIP712Domain, &MessageTypes, &Eip712Value) {
+    fn domain_separator(&self) -> Result<H256> {
+        Ok(self.0.separator())
+    }
+
+    fn message_hash(&self) -> Result<H256> {
+        let (_, message_types, value) = *self;
+        let message_type = match value {
+            Eip712Value::Struct(name, _) => Type::Custom(name.clone()),
+            _ => Err(ErrorKind::NonExistentType)?,
+        };
+        hash_struct_value(&Parser::new(), &message_type, message_types, value)
+    }
+}
+
+/// encodes and hashes the given EIP712 struct, or a domain paired with an `Eip712Struct`
+/// or with a typed `Eip712Value` struct and its MessageTypes
+pub fn hash_structured_data<D: StructuredData>(typed_data: D) -> Result<H256> {
+    typed_data.digest()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIL: &str =
+        "Mail(Person from,Person[] to,string contents)Person(string name,address[] wallets)";
+
+    #[test]
+    fn it_round_trips_encoded_types() {
+        let (primary_type, types) = parse_encoded_type(MAIL).unwrap();
+
+        assert_eq!(primary_type, "Mail");
+        assert_eq!(types["Person"].len(), 2);
+        assert_eq!(types["Mail"][1].type_, "Person[]");
+        assert_eq!(encode_type(&primary_type, &types).unwrap(), MAIL);
+
+        let domain =
+            "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+        let (primary_type, types) = parse_encoded_type(domain).unwrap();
+        assert_eq!(primary_type, "EIP712Domain");
+        assert_eq!(encode_type(&primary_type, &types).unwrap(), domain);
+    }
+
+    #[test]
+    fn it_parses_structs_without_fields() {
+        let (_, types) = parse_encoded_type("Empty()").unwrap();
+
+        assert!(types["Empty"].is_empty());
+    }
+
+    #[test]
+    fn it_rejects_unordered_or_unused_dependencies() {
+        let unordered = "Mail(Zebra z,Apple a)Zebra(bool b)Apple(bool b)";
+        let unused = "Mail(string contents)Person(string name)";
+
+        assert_eq!(
+            parse_encoded_type(unordered).unwrap_err().kind(),
+            ErrorKind::InvalidEncodedType(
+                unordered.into(),
+                "dependencies must be sorted by name, expected Mail, Apple, Zebra".into()
+            )
+        );
+        assert_eq!(
+            parse_encoded_type(unused).unwrap_err().kind(),
+            ErrorKind::InvalidEncodedType(
+                unused.into(),
+                "'Person' isn't a dependency of 'Mail'".into()
+            )
+        );
+    }
+
+    #[test]
+    fn it_rejects_malformed_encoded_types() {
+        assert_eq!(
+            parse_encoded_type("Mail(Person from)").unwrap_err().kind(),
+            ErrorKind::UnknownType("Mail.from".into(), "Person".into())
+        );
+        assert_eq!(
+            parse_encoded_type("Mail(uint amount)").unwrap_err().kind(),
+            ErrorKind::InvalidEncodedType(
+                "Mail(uint amount)".into(),
+                "'uint' isn't a canonical type name".into()
+            )
+        );
+        assert!(parse_encoded_type("Mail(string contents").is_err());
+        assert!(parse_encoded_type("Mail(string contents)Mail(string body)").is_err());
+        assert!(parse_encoded_type("Mail(string  contents)").is_err());
+        assert!(parse_encoded_type("").is_err());
+    }
 }
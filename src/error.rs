@@ -1,3 +1,6 @@
+// `failure_derive` expands to impls nested inside an anonymous const.
+#![allow(non_local_definitions)]
+
 use failure::{Backtrace, Context, Fail};
 use std::fmt::{self, Display};
 use validator::ValidationErrors;
@@ -47,6 +50,54 @@ pub enum ErrorKind {
         _0, _1, _2
     )]
     UnequalArrayItems(u64, String, u64),
+    /// an integer value doesn't fit into the bit width of its declared type
+    #[fail(display = "The value of field '{}' doesn't fit into type '{}'", _0, _1)]
+    IntegerOutOfRange(String, String),
+    /// a value doesn't fit into a bounded integer type like `Uint48`
+    #[fail(display = "The value {} doesn't fit into type '{}'", _0, _1)]
+    ValueOutOfRange(String, String),
+    /// an `encodeType` string is malformed or doesn't follow the spec's dependency ordering
+    #[fail(display = "Invalid encoded type '{}': {}", _0, _1)]
+    InvalidEncodedType(String, String),
+    /// a message is missing a field declared by its type
+    #[fail(display = "The field '{}' of type '{}' is missing", _0, _1)]
+    MissingField(String, String),
+    /// a message has a field that isn't declared by its type
+    #[fail(display = "The type '{}' has no field '{}'", _1, _0)]
+    UnknownField(String, String),
+    /// a signature is malformed or doesn't recover to a public key
+    #[fail(display = "Invalid signature: {}", _0)]
+    InvalidSignature(String),
+    /// the timestamp field of a message lies in the past
+    #[fail(display = "The deadline '{}' expired at {}", _0, _1)]
+    DeadlineExpired(String, u64),
+    /// the validity window of a message hasn't started yet
+    #[fail(display = "The message is only valid after its '{}' {}", _0, _1)]
+    NotYetValid(String, u64),
+    /// a signature recovered to an address that isn't allowed to sign
+    #[fail(display = "The signer {} isn't authorized", _0)]
+    UnauthorizedSigner(String),
+    /// a compact voucher couldn't be decoded
+    #[fail(display = "Invalid voucher encoding: {}", _0)]
+    InvalidVoucherEncoding(String),
+    /// a nonce was consumed before, i.e. a message is being replayed
+    #[fail(display = "The nonce {} was already used", _0)]
+    NonceAlreadyUsed(String),
+    /// a request's nonce isn't the one the contract expects next
+    #[fail(display = "Expected nonce {}, got {}", _0, _1)]
+    NonceMismatch(String, String),
+    /// nonces couldn't be allocated or persisted
+    #[fail(display = "Nonce storage error: {}", _0)]
+    NonceStorage(String),
+    /// a merkle tree couldn't be built or a proof doesn't match its root
+    #[fail(display = "Invalid merkle tree: {}", _0)]
+    InvalidMerkleTree(String),
+    /// a contract version this crate doesn't know how to handle
+    #[fail(display = "Unsupported version '{}'", _0)]
+    UnsupportedVersion(String),
+    /// a type or field is declared twice with different definitions
+    #[fail(display = "'{}' is declared twice", _0)]
+    DuplicateType(String),
     /// Typed array length doesn't fit into a u64
     #[fail(display = "Attempted to declare fixed size with length {}", _0)]
     InvalidArraySize(String),
@@ -0,0 +1,384 @@
+//! ERC-2771 meta-transactions for OpenZeppelin's `ERC2771Forwarder` and the
+//! older `MinimalForwarder`
+use crate::deadline::{check_deadline, Clock};
+use crate::eip712::{EIP712Domain, FieldType, MessageTypes};
+use crate::error::{ErrorKind, Result};
+use crate::signing::{recover, sign, Signature};
+use crate::typed::{Eip712Struct, Eip712Type, StructuredData, Uint48};
+use ethereum_types::{Address, H256, U256};
+use keccak_hash::keccak;
+use secp256k1::SecretKey;
+use std::time::Duration;
+
+/// the domain of an `ERC2771Forwarder` deployed with `name`
+pub fn forwarder_domain<C: Into<U256>>(
+    name: &str,
+    chain_id: C,
+    forwarder: Address,
+) -> EIP712Domain {
+    EIP712Domain::from_parts(name, "1", chain_id, forwarder)
+}
+
+/// the domain of a `MinimalForwarder`
+pub fn minimal_forwarder_domain<C: Into<U256>>(chain_id: C, forwarder: Address) -> EIP712Domain {
+    EIP712Domain::from_parts("MinimalForwarder", "0.0.1", chain_id, forwarder)
+}
+
+/// `ForwardRequest(address from,address to,uint256 value,uint256 gas,uint256 nonce,uint48 deadline,bytes data)`
+/// of OpenZeppelin's `ERC2771Forwarder`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForwardRequest {
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+    pub gas: U256,
+    pub nonce: U256,
+    /// unix seconds, inclusive
+    pub deadline: Uint48,
+    pub data: Vec<u8>,
+}
+
+impl Eip712Struct for ForwardRequest {
+    fn type_name() -> String {
+        "ForwardRequest".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("from", "address"),
+            FieldType::new("to", "address"),
+            FieldType::new("value", "uint256"),
+            FieldType::new("gas", "uint256"),
+            FieldType::new("nonce", "uint256"),
+            FieldType::new("deadline", "uint48"),
+            FieldType::new("data", "bytes"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.from.encode_word(),
+            self.to.encode_word(),
+            self.value.encode_word(),
+            self.gas.encode_word(),
+            self.nonce.encode_word(),
+            self.deadline.encode_word(),
+            self.data.encode_word(),
+        ]
+    }
+}
+
+/// `ForwardRequest(address from,address to,uint256 value,uint256 gas,uint256 nonce,bytes data)`
+/// of OpenZeppelin 4's `MinimalForwarder`, which has no deadline
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinimalForwardRequest {
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+    pub gas: U256,
+    pub nonce: U256,
+    pub data: Vec<u8>,
+}
+
+impl Eip712Struct for MinimalForwardRequest {
+    fn type_name() -> String {
+        "ForwardRequest".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("from", "address"),
+            FieldType::new("to", "address"),
+            FieldType::new("value", "uint256"),
+            FieldType::new("gas", "uint256"),
+            FieldType::new("nonce", "uint256"),
+            FieldType::new("data", "bytes"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.from.encode_word(),
+            self.to.encode_word(),
+            self.value.encode_word(),
+            self.gas.encode_word(),
+            self.nonce.encode_word(),
+            self.data.encode_word(),
+        ]
+    }
+}
+
+/// A request a relayer submits to a forwarder on behalf of `from`
+pub trait MetaTransaction: Eip712Struct {
+    /// the address that has to sign the request
+    fn from(&self) -> Address;
+
+    fn nonce(&self) -> U256;
+
+    /// fails if the request expired more than `skew` before `now`
+    fn check_expiry(&self, now: u64, skew: Duration) -> Result<()>;
+
+    /// the ABI-encoded call of the forwarder's `execute()`, send it with the
+    /// request's `value`
+    fn execute_calldata(&self, signature: &Signature) -> Vec<u8>;
+}
+
+impl MetaTransaction for ForwardRequest {
+    fn from(&self) -> Address {
+        self.from
+    }
+
+    fn nonce(&self) -> U256 {
+        self.nonce
+    }
+
+    fn check_expiry(&self, now: u64, skew: Duration) -> Result<()> {
+        check_deadline("deadline", self.deadline.value().into(), now, skew)
+    }
+
+    /// `execute((address,address,uint256,uint256,uint48,bytes,bytes))`, the
+    /// forwarder reads the nonce from its own state
+    fn execute_calldata(&self, signature: &Signature) -> Vec<u8> {
+        let request = encode_tuple(&[
+            Abi::Word(self.from.encode_word()),
+            Abi::Word(self.to.encode_word()),
+            Abi::Word(self.value.encode_word()),
+            Abi::Word(self.gas.encode_word()),
+            Abi::Word(self.deadline.encode_word()),
+            Abi::Bytes(&self.data),
+            Abi::Bytes(&signature.to_bytes()),
+        ]);
+        call(
+            "execute((address,address,uint256,uint256,uint48,bytes,bytes))",
+            &[Abi::Tuple(request)],
+        )
+    }
+}
+
+impl MetaTransaction for MinimalForwardRequest {
+    fn from(&self) -> Address {
+        self.from
+    }
+
+    fn nonce(&self) -> U256 {
+        self.nonce
+    }
+
+    fn check_expiry(&self, _now: u64, _skew: Duration) -> Result<()> {
+        Ok(())
+    }
+
+    /// `execute((address,address,uint256,uint256,uint256,bytes),bytes)`
+    fn execute_calldata(&self, signature: &Signature) -> Vec<u8> {
+        let request = encode_tuple(&[
+            Abi::Word(self.from.encode_word()),
+            Abi::Word(self.to.encode_word()),
+            Abi::Word(self.value.encode_word()),
+            Abi::Word(self.gas.encode_word()),
+            Abi::Word(self.nonce.encode_word()),
+            Abi::Bytes(&self.data),
+        ]);
+        call(
+            "execute((address,address,uint256,uint256,uint256,bytes),bytes)",
+            &[Abi::Tuple(request), Abi::Bytes(&signature.to_bytes())],
+        )
+    }
+}
+
+/// an ABI value, either a static word or a dynamic value stored in the tail
+enum Abi<'a> {
+    Word(H256),
+    Bytes(&'a [u8]),
+    /// an already encoded dynamic tuple
+    Tuple(Vec<u8>),
+}
+
+fn encode_tuple(values: &[Abi]) -> Vec<u8> {
+    let head_len = 32 * values.len();
+    let mut head = Vec::with_capacity(head_len);
+    let mut tail = vec![];
+    for value in values {
+        let offset = U256::from(head_len + tail.len()).encode_word();
+        match value {
+            Abi::Word(word) => head.extend_from_slice(&word.0),
+            Abi::Bytes(bytes) => {
+                head.extend_from_slice(&offset.0);
+                tail.extend_from_slice(&U256::from(bytes.len()).encode_word().0);
+                tail.extend_from_slice(bytes);
+                tail.resize(tail.len() + (32 - bytes.len() % 32) % 32, 0);
+            }
+            Abi::Tuple(encoded) => {
+                head.extend_from_slice(&offset.0);
+                tail.extend_from_slice(encoded);
+            }
+        }
+    }
+    head.extend(tail);
+    head
+}
+
+fn call(signature: &str, arguments: &[Abi]) -> Vec<u8> {
+    let mut calldata = keccak(signature).0[..4].to_vec();
+    calldata.extend(encode_tuple(arguments));
+    calldata
+}
+
+/// Signs `request` for the forwarder at `domain`
+pub fn sign_request<R: MetaTransaction>(
+    domain: &EIP712Domain,
+    request: &R,
+    secret_key: &SecretKey,
+) -> Result<Signature> {
+    Ok(sign(&(domain, request).digest()?, secret_key))
+}
+
+/// Checks `request` the way the forwarder's `verify()` does: it hasn't expired,
+/// its nonce is the forwarder's current `nonces(from)` and `from` signed it
+pub fn verify_request<R: MetaTransaction, C: Clock>(
+    domain: &EIP712Domain,
+    request: &R,
+    signature: &Signature,
+    current_nonce: U256,
+    clock: &C,
+) -> Result<()> {
+    request.check_expiry(clock.now(), clock.skew())?;
+    if request.nonce() != current_nonce {
+        Err(ErrorKind::NonceMismatch(
+            format!("{}", current_nonce),
+            format!("{}", request.nonce()),
+        ))?;
+    }
+    let signer = recover(&(domain, request).digest()?, signature)?;
+    if signer != request.from() {
+        Err(ErrorKind::UnauthorizedSigner(format!("0x{:x}", signer)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deadline::FakeClock;
+    use crate::signing::{address, test_key};
+
+    fn forwarder() -> Address {
+        Address::from(&[0xf0; 20][..])
+    }
+
+    fn request() -> ForwardRequest {
+        ForwardRequest {
+            from: address(&test_key()),
+            to: Address::from(&[0x22; 20][..]),
+            value: 0.into(),
+            gas: 100_000.into(),
+            nonce: 3.into(),
+            deadline: 1_700_000_000.into(),
+            // transfer(address,uint256)
+            data: vec![0xa9, 0x05, 0x9c, 0xbb],
+        }
+    }
+
+    #[test]
+    fn it_signs_and_verifies_forward_requests() {
+        let domain = forwarder_domain("Relayer", 1u64, forwarder());
+        let request = request();
+        let signature = sign_request(&domain, &request, &test_key()).unwrap();
+        let clock = FakeClock::new(1_700_000_000);
+
+        assert_eq!(
+            ForwardRequest::encode_type(),
+            "ForwardRequest(address from,address to,uint256 value,uint256 gas,uint256 nonce,uint48 deadline,bytes data)"
+        );
+        verify_request(&domain, &request, &signature, 3.into(), &clock).unwrap();
+        assert_eq!(
+            verify_request(&domain, &request, &signature, 4.into(), &clock)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NonceMismatch("4".into(), "3".into())
+        );
+
+        let mut other_call = request.clone();
+        other_call.data.push(0);
+        assert!(verify_request(&domain, &other_call, &signature, 3.into(), &clock).is_err());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(verify_request(&domain, &request, &signature, 3.into(), &clock).is_err());
+    }
+
+    #[test]
+    fn it_encodes_execute_calldata() {
+        let request = request();
+        let signature = Signature {
+            r: H256::from(&[0xaa; 32][..]),
+            s: H256::from(&[0xbb; 32][..]),
+            v: 27,
+        };
+        let calldata = request.execute_calldata(&signature);
+        let word = |index: usize| &calldata[4 + 32 * index..4 + 32 * (index + 1)];
+
+        assert_eq!(calldata[..4], [0xdf, 0x90, 0x5c, 0xaf]);
+        // the offset of the request tuple, then its head
+        assert_eq!(word(0), U256::from(0x20).encode_word().0);
+        assert_eq!(word(1), request.from.encode_word().0);
+        assert_eq!(word(5), U256::from(1_700_000_000).encode_word().0);
+        // data and signature offsets, relative to the tuple
+        assert_eq!(word(6), U256::from(7 * 32).encode_word().0);
+        assert_eq!(word(7), U256::from(9 * 32).encode_word().0);
+        assert_eq!(word(8), U256::from(4).encode_word().0);
+        assert_eq!(word(9)[..4], [0xa9, 0x05, 0x9c, 0xbb]);
+        assert_eq!(word(10), U256::from(65).encode_word().0);
+        assert_eq!(word(11), [0xaa; 32]);
+        assert_eq!(word(13)[0], 27);
+        assert_eq!(calldata.len(), 4 + 32 * 14);
+    }
+
+    #[test]
+    fn it_supports_the_minimal_forwarder() {
+        let domain = minimal_forwarder_domain(1u64, forwarder());
+        let request = MinimalForwardRequest {
+            from: address(&test_key()),
+            to: Address::from(&[0x22; 20][..]),
+            value: 0.into(),
+            gas: 100_000.into(),
+            nonce: 0.into(),
+            data: vec![],
+        };
+        let signature = sign_request(&domain, &request, &test_key()).unwrap();
+
+        assert_eq!(
+            MinimalForwardRequest::encode_type(),
+            "ForwardRequest(address from,address to,uint256 value,uint256 gas,uint256 nonce,bytes data)"
+        );
+        verify_request(
+            &domain,
+            &request,
+            &signature,
+            0.into(),
+            &FakeClock::new(u64::MAX),
+        )
+        .unwrap();
+        assert!(verify_request(
+            &forwarder_domain("MinimalForwarder", 1u64, forwarder()),
+            &request,
+            &signature,
+            0.into(),
+            &FakeClock::new(0)
+        )
+        .is_err());
+
+        let calldata = request.execute_calldata(&signature);
+        let word = |index: usize| &calldata[4 + 32 * index..4 + 32 * (index + 1)];
+        assert_eq!(calldata[..4], [0x47, 0x15, 0x3f, 0x82]);
+        assert_eq!(word(0), U256::from(0x40).encode_word().0);
+        // the request tuple: six words and an empty `data`
+        assert_eq!(word(1), U256::from(0x40 + 7 * 32).encode_word().0);
+        assert_eq!(word(7), U256::from(6 * 32).encode_word().0);
+        assert_eq!(word(8), [0; 32]);
+        assert_eq!(word(9), U256::from(65).encode_word().0);
+        assert_eq!(calldata.len(), 4 + 32 * 13);
+    }
+}
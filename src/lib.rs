@@ -2,13 +2,32 @@ mod encode;
 mod error;
 mod parser;
 
-pub use crate::encode::hash_structured_data;
+pub use crate::encode::{encode_type, hash_structured_data, parse_encoded_type};
 pub use crate::error::{Error, ErrorKind};
 pub use crate::nft_helpers::*;
 
+pub mod authorization;
+pub mod batch;
+pub mod deadline;
+pub mod eip191;
 pub mod eip712;
+pub mod forwarder;
+pub mod governance;
+pub mod merkle;
 pub mod nft_helpers;
+pub mod nonce;
+pub mod permit;
+pub mod permit2;
+pub mod safe;
+pub mod schema;
+pub mod seaport;
 pub mod signing;
+pub mod solidity;
+pub mod typed;
+pub mod typescript;
+pub mod user_operation;
+pub mod value;
+pub mod voucher;
 
 // Re-export EIP712 and EIP712Domain
 pub use eip712::{EIP712Domain, FieldType, MessageTypes, EIP712};
@@ -17,7 +36,95 @@ pub use eip712::{EIP712Domain, FieldType, MessageTypes, EIP712};
 pub use nft_helpers::hash_structured_data_string;
 
 // Re-export functions from signing
-pub use signing::sign_message;
+pub use signing::{recover, sign, sign_hash, sign_message, Signature};
+
+// Re-export EIP-191 signed data
+pub use eip191::{
+    personal_message_hash, recover_personal_message, sign_personal_message, SignedData,
+};
+
+// Re-export the typed struct trait and its derive macro
+#[cfg(feature = "derive")]
+pub use eip_712_utils_derive::Eip712;
+pub use typed::{Eip712Integer, Eip712Struct, Eip712Type, StructuredData, Uint160, Uint48, Uint8};
+pub use value::Eip712Value;
+
+// Re-export functions from solidity
+pub use solidity::{generate_solidity, parse_solidity_structs};
+
+// Re-export functions from schema
+pub use schema::generate_json_schema;
+
+// Re-export functions from typescript
+pub use typescript::generate_typescript;
+
+// Re-export functions from batch
+pub use batch::{hash_batch, sign_batch, SignedMessage};
+
+// Re-export the voucher lifecycle
+pub use voucher::{redeem_voucher, verify_voucher, NftData, SignedVoucher, Voucher, VoucherIssuer};
+
+// Re-export deadline handling
+pub use deadline::{Clock, DeadlineCheck, FakeClock, SystemClock, WithSkew};
+
+// Re-export merkle batches
+pub use merkle::{
+    leaf_hash, sign_merkle_batch, verify_batch_member, verify_merkle_proof, BatchProof,
+    MerkleBatch, MerkleTree,
+};
+
+// Re-export ERC-20 permits
+pub use permit::{sign_permit, verify_permit, DaiPermit, Permit, PermitMessage};
+
+// Re-export Permit2
+pub use permit2::{
+    permit2_domain, PermitBatch, PermitBatchTransferFrom, PermitDetails, PermitSingle,
+    PermitTransferFrom, PermitWitness, TokenPermissions, TransferPermit, Witness, PERMIT2_ADDRESS,
+};
+
+// Re-export EIP-3009 authorizations
+pub use authorization::{
+    random_nonce, sign_authorization, verify_authorization, CancelAuthorization,
+    ReceiveWithAuthorization, TransferAuthorization, TransferWithAuthorization,
+};
+
+// Re-export ERC-2771 meta-transactions
+pub use forwarder::{
+    forwarder_domain, minimal_forwarder_domain, sign_request, verify_request, ForwardRequest,
+    MetaTransaction, MinimalForwardRequest,
+};
+
+// Re-export Safe hashing
+pub use safe::{
+    encode_signatures, Operation, Safe, SafeMessage, SafeSignature, SafeTx, SafeVersion,
+};
+
+// Re-export Seaport orders
+pub use seaport::{
+    order_schema, seaport_domain, BulkOrder, ConsiderationItem, ItemType, OfferItem,
+    OrderComponents, OrderType,
+};
+
+// Re-export governance votes and delegations
+pub use governance::{
+    bravo_domain, governor_domain, sign_governance, verify_delegation, verify_vote,
+    verify_voter_ballot, Ballot, BallotV5, Delegation, ExtendedBallot, ExtendedBallotV5, VoteType,
+    VoterBallot,
+};
+
+// Re-export ERC-4337 user operations
+pub use user_operation::{
+    entry_point_domain, entry_point_v08_domain, PackedUserOperation, UserOperation, EIP7702_MARKER,
+    ENTRY_POINT_V08_ADDRESS,
+};
+
+// Re-export nonce management
+pub use nonce::{FileNonceManager, InMemoryNonceManager, NonceManager, NonceScope, NonceStrategy};
+
+#[doc(hidden)]
+pub mod __private {
+    pub use ethereum_types::H256;
+}
 
 #[cfg(test)]
 mod tests {
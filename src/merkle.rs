@@ -0,0 +1,320 @@
+//! Merkle batches: many messages authorized by a single signature over a root.
+//!
+//! The tree is compatible with OpenZeppelin's `StandardMerkleTree` for leaves of
+//! type `bytes32`: every leaf is `keccak256(keccak256(abi.encode(structHash)))`,
+//! leaves are sorted and pairs are hashed in sorted order, so a contract checks
+//! a member with `MerkleProof.verify(proof, root, leaf)`.
+use crate::eip712::{EIP712Domain, FieldType, MessageTypes};
+use crate::error::{ErrorKind, Result};
+use crate::signing::{recover, sign, Signature};
+use crate::typed::{Eip712Struct, Eip712Type, StructuredData};
+use ethereum_types::{Address, H256};
+use keccak_hash::keccak;
+use secp256k1::SecretKey;
+
+/// the OpenZeppelin leaf of an EIP-712 struct hash
+pub fn leaf_hash(struct_hash: &H256) -> H256 {
+    keccak(keccak(struct_hash))
+}
+
+fn hash_pair(a: &H256, b: &H256) -> H256 {
+    let (first, second) = if a < b { (a, b) } else { (b, a) };
+    let mut concatenated = [0u8; 64];
+    concatenated[..32].copy_from_slice(&first.0);
+    concatenated[32..].copy_from_slice(&second.0);
+    keccak(&concatenated[..])
+}
+
+/// whether `proof` leads from `leaf` to `root`, like OpenZeppelin's `MerkleProof.verify`
+pub fn verify_merkle_proof(root: &H256, leaf: &H256, proof: &[H256]) -> bool {
+    proof
+        .iter()
+        .fold(*leaf, |computed, sibling| hash_pair(&computed, sibling))
+        == *root
+}
+
+/// A `StandardMerkleTree` over struct hashes
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    /// the flattened tree, root first and leaves at the end
+    tree: Vec<H256>,
+    /// the tree position of every struct hash, in input order
+    positions: Vec<usize>,
+}
+
+impl MerkleTree {
+    pub fn from_struct_hashes(struct_hashes: &[H256]) -> Result<Self> {
+        Self::from_leaves(&struct_hashes.iter().map(leaf_hash).collect::<Vec<_>>())
+    }
+
+    /// a tree over already hashed leaves, e.g. `keccak256(keccak256(abi.encode(...)))`
+    /// of a `StandardMerkleTree` with other leaf types than `bytes32`
+    pub fn from_leaves(leaves: &[H256]) -> Result<Self> {
+        if leaves.is_empty() {
+            Err(ErrorKind::InvalidMerkleTree(
+                "expected at least one leaf".into(),
+            ))?;
+        }
+        let count = leaves.len();
+        let mut leaves = leaves.iter().copied().enumerate().collect::<Vec<_>>();
+        leaves.sort_by_key(|(_, leaf)| *leaf);
+
+        let len = 2 * leaves.len() - 1;
+        let mut tree = vec![H256::zero(); len];
+        let mut positions = vec![0; leaves.len()];
+        for (sorted, (index, leaf)) in leaves.into_iter().enumerate() {
+            tree[len - 1 - sorted] = leaf;
+            positions[index] = len - 1 - sorted;
+        }
+        for i in (0..len - count).rev() {
+            tree[i] = hash_pair(&tree[2 * i + 1], &tree[2 * i + 2]);
+        }
+
+        Ok(MerkleTree { tree, positions })
+    }
+
+    pub fn root(&self) -> H256 {
+        self.tree[0]
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// the proof of the `index`th struct hash, in the order the tree was built
+    /// from; fails if there are only `index` leaves or fewer
+    pub fn proof(&self, index: usize) -> Result<Vec<H256>> {
+        let mut position = *self.positions.get(index).ok_or_else(|| {
+            ErrorKind::InvalidMerkleTree(format!(
+                "no leaf {} in a tree of {} leaves",
+                index,
+                self.len()
+            ))
+        })?;
+        let mut proof = vec![];
+        while position > 0 {
+            let sibling = if position % 2 == 1 {
+                position + 1
+            } else {
+                position - 1
+            };
+            proof.push(self.tree[sibling]);
+            position = (position - 1) / 2;
+        }
+        Ok(proof)
+    }
+}
+
+/// The EIP-712 envelope the root is signed in, `MerkleBatch(bytes32 root)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MerkleBatch {
+    pub root: H256,
+}
+
+impl Eip712Struct for MerkleBatch {
+    fn type_name() -> String {
+        "MerkleBatch".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![FieldType {
+            name: "root".to_string(),
+            type_: "bytes32".to_string(),
+        }]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![self.root.encode_word()]
+    }
+}
+
+/// What a single recipient needs to prove their message is part of a signed batch
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchProof {
+    pub root: H256,
+    pub signature: Signature,
+    pub proof: Vec<H256>,
+}
+
+/// Builds a tree over `messages`, signs its root and returns one proof per
+/// message, in input order
+pub fn sign_merkle_batch<T: Eip712Struct>(
+    domain: &EIP712Domain,
+    messages: &[T],
+    secret_key: &SecretKey,
+) -> Result<Vec<BatchProof>> {
+    let struct_hashes = messages
+        .iter()
+        .map(Eip712Struct::struct_hash)
+        .collect::<Vec<_>>();
+    let tree = MerkleTree::from_struct_hashes(&struct_hashes)?;
+    let root = tree.root();
+    let signature = sign(&(domain, &MerkleBatch { root }).digest()?, secret_key);
+
+    (0..tree.len())
+        .map(|index| {
+            Ok(BatchProof {
+                root,
+                signature,
+                proof: tree.proof(index)?,
+            })
+        })
+        .collect()
+}
+
+/// Checks that `message` is a leaf of the batch and that the batch root was
+/// signed by one of `authorized_signers`, returning the signer
+pub fn verify_batch_member<T: Eip712Struct>(
+    domain: &EIP712Domain,
+    message: &T,
+    proof: &BatchProof,
+    authorized_signers: &[Address],
+) -> Result<Address> {
+    if !verify_merkle_proof(
+        &proof.root,
+        &leaf_hash(&message.struct_hash()),
+        &proof.proof,
+    ) {
+        Err(ErrorKind::InvalidMerkleTree(
+            "the proof doesn't lead to the root".into(),
+        ))?;
+    }
+    let digest = (domain, &MerkleBatch { root: proof.root }).digest()?;
+    let signer = recover(&digest, &proof.signature)?;
+    if !authorized_signers.contains(&signer) {
+        Err(ErrorKind::UnauthorizedSigner(format!("0x{:x}", signer)))?;
+    }
+    Ok(signer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::{address, test_key};
+    use crate::voucher::NftData;
+    use ethereum_types::U256;
+
+    fn hashes(len: u8) -> Vec<H256> {
+        (0..len).map(|i| keccak([i])).collect()
+    }
+
+    #[test]
+    fn it_lays_out_the_tree_like_openzeppelin() {
+        let hashes = hashes(3);
+        let mut leaves = hashes.iter().map(leaf_hash).collect::<Vec<_>>();
+        leaves.sort();
+        // leaves are stored in reverse at the end of the array: [root, n1, l2, l1, l0]
+        let expected = hash_pair(&hash_pair(&leaves[1], &leaves[0]), &leaves[2]);
+
+        let tree = MerkleTree::from_struct_hashes(&hashes).unwrap();
+        assert_eq!(tree.root(), expected);
+        assert_eq!(
+            MerkleTree::from_struct_hashes(&hashes[..1]).unwrap().root(),
+            leaf_hash(&hashes[0])
+        );
+        assert!(MerkleTree::from_struct_hashes(&[]).is_err());
+    }
+
+    #[test]
+    fn it_matches_the_openzeppelin_readme_tree() {
+        // StandardMerkleTree.of([[0x1111…, "5000000000000000000"], [0x2222…, "2500000000000000000"]],
+        // ["address", "uint256"]) from the @openzeppelin/merkle-tree README
+        let values = [
+            (
+                Address::from(&[0x11; 20][..]),
+                U256::from(5_000_000_000_000_000_000u64),
+            ),
+            (
+                Address::from(&[0x22; 20][..]),
+                U256::from(2_500_000_000_000_000_000u64),
+            ),
+        ];
+        let leaves = values
+            .iter()
+            .map(|(account, amount)| {
+                let encoded = [account.encode_word().0, amount.encode_word().0].concat();
+                keccak(keccak(&encoded))
+            })
+            .collect::<Vec<_>>();
+        let tree = MerkleTree::from_leaves(&leaves).unwrap();
+
+        assert_eq!(
+            format!("{:x}", tree.root()),
+            "d4dee0beab2d53f2cc83e567171bd2820e49898130a22622b10ead383e90bd77"
+        );
+        assert_eq!(tree.proof(0).unwrap(), vec![leaves[1]]);
+        assert!(verify_merkle_proof(
+            &tree.root(),
+            &leaves[0],
+            &tree.proof(0).unwrap()
+        ));
+        assert!(verify_merkle_proof(
+            &tree.root(),
+            &leaves[1],
+            &tree.proof(1).unwrap()
+        ));
+    }
+
+    #[test]
+    fn it_proves_every_leaf() {
+        for len in 1..=17 {
+            let hashes = hashes(len);
+            let tree = MerkleTree::from_struct_hashes(&hashes).unwrap();
+
+            for (index, hash) in hashes.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert!(verify_merkle_proof(&tree.root(), &leaf_hash(hash), &proof));
+                // the single hashed struct hash must not pass as a leaf
+                assert!(len == 1 || !verify_merkle_proof(&tree.root(), &keccak(hash), &proof));
+            }
+            assert_eq!(
+                tree.proof(hashes.len()).unwrap_err().kind(),
+                ErrorKind::InvalidMerkleTree(format!(
+                    "no leaf {} in a tree of {} leaves",
+                    len, len
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn it_signs_and_verifies_batch_members() {
+        let domain = EIP712Domain::new(
+            "AionRisingNFTs",
+            "0.0.1",
+            "0x7A69",
+            "0x037eDa3aDB1198021A9b2e88C22B464fD38db3f3",
+        );
+        let secret_key = test_key();
+        let vouchers = (0..5u64)
+            .map(|i| NftData {
+                token_id: U256::from(i),
+                amount: 1.into(),
+                to: Address::from(&[i as u8; 20][..]),
+                nonce: U256::from(i),
+            })
+            .collect::<Vec<_>>();
+
+        let proofs = sign_merkle_batch(&domain, &vouchers, &secret_key).unwrap();
+        let signers = [address(&secret_key)];
+        for (voucher, proof) in vouchers.iter().zip(&proofs) {
+            assert_eq!(
+                verify_batch_member(&domain, voucher, proof, &signers).unwrap(),
+                signers[0]
+            );
+        }
+
+        // a proof only works for its own voucher
+        assert!(verify_batch_member(&domain, &vouchers[0], &proofs[1], &signers).is_err());
+        let mut forged = proofs[0].clone();
+        forged.root = keccak("forged");
+        assert!(verify_batch_member(&domain, &vouchers[0], &forged, &signers).is_err());
+        assert!(verify_batch_member(&domain, &vouchers[0], &proofs[0], &[]).is_err());
+    }
+}
@@ -1,68 +1,264 @@
-use crate::eip712::EIP712;
+use crate::deadline::{check_deadline, Clock};
+use crate::eip712::{EIP712Domain, FieldType, MessageTypes, EIP712};
 use crate::encode::hash_structured_data;
+use crate::error::{ErrorKind, Result};
+use crate::signing::{recover, sign, Signature};
+use crate::typed::{Eip712Struct, Eip712Type, StructuredData};
+use crate::value::Eip712Value;
+use ethereum_types::{Address, H256, U256};
 use rustc_hex::ToHex;
-use serde_json::from_str;
+use secp256k1::SecretKey;
+use serde_json::{from_str, json, to_value, Map, Value};
+use std::collections::HashMap;
 
+/// A primary type together with the struct types it depends on
+#[derive(Debug, Clone)]
+pub struct MessageSchema {
+    pub primary_type: String,
+    pub types: MessageTypes,
+}
+
+impl MessageSchema {
+    pub fn new(primary_type: &str, fields: Vec<FieldType>) -> Self {
+        let mut types = HashMap::new();
+        types.insert(primary_type.to_owned(), fields);
+        MessageSchema {
+            primary_type: primary_type.to_owned(),
+            types,
+        }
+    }
+
+    /// adds a struct type the primary type depends on
+    pub fn with_type(mut self, type_name: &str, fields: Vec<FieldType>) -> Self {
+        self.types.insert(type_name.to_owned(), fields);
+        self
+    }
+
+    /// the `NFTData` voucher
+    pub fn nft_data() -> Self {
+        MessageSchema::new(
+            "NFTData",
+            vec![
+                FieldType::new("tokenId", "uint256"),
+                FieldType::new("amount", "uint256"),
+                FieldType::new("to", "address"),
+                FieldType::new("nonce", "uint256"),
+            ],
+        )
+    }
+
+    /// the EIP-4494 `Permit` of a single token
+    pub fn nft_permit() -> Self {
+        MessageSchema::new(
+            "Permit",
+            vec![
+                FieldType::new("spender", "address"),
+                FieldType::new("tokenId", "uint256"),
+                FieldType::new("nonce", "uint256"),
+                FieldType::new("deadline", "uint256"),
+            ],
+        )
+    }
+}
+
+/// Builds the message for `schema` from its field values. Fails if a declared
+/// field is missing, an undeclared one is given, or a value doesn't match its type.
+pub fn create_typed_message<I, K, V>(schema: &MessageSchema, values: I) -> Result<Value>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<Value>,
+{
+    let mut values = values
+        .into_iter()
+        .map(|(name, value)| (name.into(), value.into()))
+        .collect::<HashMap<String, Value>>();
+    let fields = schema
+        .types
+        .get(&schema.primary_type)
+        .ok_or(ErrorKind::NonExistentType)?;
+
+    let mut message = Map::new();
+    for field in fields {
+        let value = values.remove(&field.name).ok_or_else(|| {
+            ErrorKind::MissingField(field.name.clone(), schema.primary_type.clone())
+        })?;
+        message.insert(field.name.clone(), value);
+    }
+    if let Some(name) = values.into_keys().next() {
+        Err(ErrorKind::UnknownField(name, schema.primary_type.clone()))?;
+    }
+
+    let message = Value::Object(message);
+    Eip712Value::from_json(&message, &schema.primary_type, &schema.types)?;
+    Ok(message)
+}
+
+/// Builds the complete typed data object for `message`, as accepted by
+/// `eth_signTypedData_v4` and `hash_structured_data_string`. `EIP712Domain`
+/// only lists the fields `domain` sets.
+pub fn generate_typed_data(
+    domain: &EIP712Domain,
+    schema: &MessageSchema,
+    message: Value,
+) -> Result<Value> {
+    Eip712Value::from_json(&message, &schema.primary_type, &schema.types)?;
+    let domain = to_value(domain).expect("EIP712Domain always serializes; qed");
+    Ok(typed_data(domain, schema, message))
+}
+
+fn typed_data(domain: Value, schema: &MessageSchema, message: Value) -> Value {
+    let domain_fields = [
+        ("name", "string"),
+        ("version", "string"),
+        ("chainId", "uint256"),
+        ("verifyingContract", "address"),
+        ("salt", "bytes32"),
+    ]
+    .iter()
+    .filter(|(name, _)| domain.get(name).is_some())
+    .map(|(name, type_)| json!({ "name": name, "type": type_ }))
+    .collect::<Vec<_>>();
+
+    let mut types = Map::new();
+    types.insert("EIP712Domain".into(), Value::Array(domain_fields));
+    for (name, fields) in &schema.types {
+        types.insert(
+            name.clone(),
+            to_value(fields).expect("FieldType always serializes; qed"),
+        );
+    }
+
+    json!({
+        "primaryType": schema.primary_type,
+        "domain": domain,
+        "message": message,
+        "types": types,
+    })
+}
+
+/// the domain JSON with `chain_id` and `verifying_contract` expected as hex strings,
+/// prefer `EIP712Domain::from_parts` and `generate_typed_data`
 pub fn create_domain(
     name: &str,
     version: &str,
     chain_id: &str,
     verifying_contract: &str,
 ) -> String {
-    format!(
-        r#"{{
-        "name": "{}",
-        "version": "{}",
-        "chainId": "{}",
-        "verifyingContract": "{}"
-    }}"#,
-        name, version, chain_id, verifying_contract
-    )
+    json!({
+        "name": name,
+        "version": version,
+        "chainId": chain_id,
+        "verifyingContract": verifying_contract,
+    })
+    .to_string()
 }
 
+/// the `NFTData` message, see `create_typed_message` for other schemas
 pub fn create_message(token_id: &str, amount: &str, to: &str, nonce: &str) -> String {
-    format!(
-        r#"{{
-        "tokenId": "{}",
-        "amount": "{}",
-        "to": "{}",
-        "nonce": "{}"
-    }}"#,
-        token_id, amount, to, nonce
-    )
+    json!({
+        "tokenId": token_id,
+        "amount": amount,
+        "to": to,
+        "nonce": nonce,
+    })
+    .to_string()
 }
 
+/// the `NFTData` typed data for the given domain and message JSON, see
+/// `generate_typed_data` for other schemas
+///
+/// # Panics
+///
+/// if `domain` or `message` isn't valid JSON
 pub fn generate_eip712_json_string(domain: &str, message: &str) -> String {
-    let json = format!(
-        r#"{{
-        "primaryType": "NFTData",
-        "domain": {},
-        "message": {},
-        "types": {{
-            "EIP712Domain": [
-                {{ "name": "name", "type": "string" }},
-                {{ "name": "version", "type": "string" }},
-                {{ "name": "chainId", "type": "uint256" }},
-                {{ "name": "verifyingContract", "type": "address" }}
-            ],
-            "NFTData": [
-                {{ "name": "tokenId", "type": "uint256" }},
-                {{ "name": "amount", "type": "uint256" }},
-                {{ "name": "to", "type": "address" }},
-                {{ "name": "nonce", "type": "uint256" }}
-            ]
-        }}
-    }}"#,
-        domain, message
-    );
-
-    json
+    let domain = from_str(domain).expect("domain must be valid JSON");
+    let message = from_str(message).expect("message must be valid JSON");
+    typed_data(domain, &MessageSchema::nft_data(), message).to_string()
 }
 
 pub fn hash_structured_data_string(json: String) -> String {
     let typed_data = from_str::<EIP712>(&json).unwrap();
-    let result = hash_structured_data(typed_data).unwrap().to_hex::<String>();
-    result
+    hash_structured_data(typed_data).unwrap().to_hex::<String>()
+}
+
+/// `Permit(address spender,uint256 tokenId,uint256 nonce,uint256 deadline)` as
+/// defined by EIP-4494, approves `spender` for a single token. The token's owner
+/// isn't part of the message, and the nonce is the token's, not the owner's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NftPermit {
+    pub spender: Address,
+    pub token_id: U256,
+    /// the token's `nonces(tokenId)`, incremented on every transfer
+    pub nonce: U256,
+    pub deadline: U256,
+}
+
+impl NftPermit {
+    /// the message as JSON
+    pub fn message(&self) -> Value {
+        json!({
+            "spender": format!("0x{:x}", self.spender),
+            "tokenId": format!("0x{:x}", self.token_id),
+            "nonce": format!("0x{:x}", self.nonce),
+            "deadline": format!("0x{:x}", self.deadline),
+        })
+    }
+
+    /// the typed data JSON to pass to `eth_signTypedData_v4`
+    pub fn typed_data(&self, domain: &EIP712Domain) -> Value {
+        generate_typed_data(domain, &MessageSchema::nft_permit(), self.message())
+            .expect("permits always match their schema; qed")
+    }
+}
+
+impl Eip712Struct for NftPermit {
+    fn type_name() -> String {
+        "Permit".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        MessageSchema::nft_permit().types["Permit"].clone()
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.spender.encode_word(),
+            self.token_id.encode_word(),
+            self.nonce.encode_word(),
+            self.deadline.encode_word(),
+        ]
+    }
+}
+
+/// Signs `permit` for the NFT contract at `domain`; the signature is the last
+/// argument of the contract's `permit()`
+pub fn sign_nft_permit(
+    domain: &EIP712Domain,
+    permit: &NftPermit,
+    secret_key: &SecretKey,
+) -> Result<Signature> {
+    Ok(sign(&(domain, permit).digest()?, secret_key))
+}
+
+/// Checks that `permit` hasn't expired according to `clock` and was signed by
+/// one of `authorized_signers`, i.e. the token's owner or its approved
+/// operators, returning the signer
+pub fn verify_nft_permit<C: Clock>(
+    domain: &EIP712Domain,
+    permit: &NftPermit,
+    signature: &Signature,
+    authorized_signers: &[Address],
+    clock: &C,
+) -> Result<Address> {
+    check_deadline("deadline", permit.deadline, clock.now(), clock.skew())?;
+    let signer = recover(&(domain, permit).digest()?, signature)?;
+    if !authorized_signers.contains(&signer) {
+        Err(ErrorKind::UnauthorizedSigner(format!("0x{:x}", signer)))?;
+    }
+    Ok(signer)
 }
 
 #[cfg(test)]
@@ -71,6 +267,7 @@ mod tests {
     use crate::FieldType;
 
     use super::*;
+    use crate::signing::test_key;
     use rustc_hex::ToHex;
     use serde_json::json;
 
@@ -120,7 +317,7 @@ mod tests {
         }"#;
 
         let v1: serde_json::Value = from_str(&json).unwrap();
-        let v2: serde_json::Value = from_str(&expected_json).unwrap();
+        let v2: serde_json::Value = from_str(expected_json).unwrap();
         assert_eq!(v1, v2);
     }
 
@@ -171,4 +368,181 @@ mod tests {
             "77915d20c811f39572463a234db9b776d518d07d9682a825be0d79752745a4c7"
         );
     }
+
+    fn quest_schema() -> MessageSchema {
+        MessageSchema::new(
+            "Quest",
+            vec![
+                FieldType {
+                    name: "title".to_string(),
+                    type_: "string".to_string(),
+                },
+                FieldType {
+                    name: "player".to_string(),
+                    type_: "Player".to_string(),
+                },
+            ],
+        )
+        .with_type(
+            "Player",
+            vec![FieldType {
+                name: "wallet".to_string(),
+                type_: "address".to_string(),
+            }],
+        )
+    }
+
+    #[test]
+    fn it_creates_messages_for_any_schema() {
+        let title = r#"Slay", "player": { "wallet": "0x0000000000000000000000000000000000000000" }, "x": ""#;
+        let player = json!({ "wallet": "0x7FA9385bE102ac3EAc297483Dd6233D62b3e1496" });
+        let schema = quest_schema();
+        let domain = EIP712Domain::new(
+            "AionRisingNFTs",
+            "0.0.1",
+            "0x7A69",
+            "0x037eDa3aDB1198021A9b2e88C22B464fD38db3f3",
+        );
+
+        let message = create_typed_message(
+            &schema,
+            vec![("title", json!(title)), ("player", player.clone())],
+        )
+        .unwrap();
+        assert_eq!(message, json!({ "title": title, "player": player }));
+
+        let typed_data = generate_typed_data(&domain, &schema, message).unwrap();
+        assert_eq!(
+            typed_data["types"]["EIP712Domain"]
+                .as_array()
+                .unwrap()
+                .len(),
+            4
+        );
+        assert_eq!(typed_data["types"]["Player"][0]["name"], "wallet");
+
+        let typed_data = serde_json::from_value::<EIP712>(typed_data).unwrap();
+        let expected = hash_structured_data((
+            &domain,
+            &schema.types,
+            &Eip712Value::new_struct(
+                "Quest",
+                vec![
+                    ("title", Eip712Value::from(title)),
+                    (
+                        "player",
+                        Eip712Value::new_struct(
+                            "Player",
+                            vec![(
+                                "wallet",
+                                Eip712Value::Address(
+                                    "7FA9385bE102ac3EAc297483Dd6233D62b3e1496".parse().unwrap(),
+                                ),
+                            )],
+                        ),
+                    ),
+                ],
+            ),
+        ))
+        .unwrap();
+        assert_eq!(hash_structured_data(typed_data).unwrap(), expected);
+    }
+
+    #[test]
+    fn it_rejects_missing_unknown_and_invalid_fields() {
+        let schema = quest_schema();
+        let player = json!({ "wallet": "0x7FA9385bE102ac3EAc297483Dd6233D62b3e1496" });
+
+        assert_eq!(
+            create_typed_message(&schema, vec![("title", json!("Slay"))])
+                .unwrap_err()
+                .kind(),
+            ErrorKind::MissingField("player".into(), "Quest".into())
+        );
+        assert_eq!(
+            create_typed_message(
+                &schema,
+                vec![
+                    ("title", json!("Slay")),
+                    ("player", player),
+                    ("reward", json!("0x1"))
+                ]
+            )
+            .unwrap_err()
+            .kind(),
+            ErrorKind::UnknownField("reward".into(), "Quest".into())
+        );
+        assert!(create_typed_message(
+            &schema,
+            vec![("title", json!("Slay")), ("player", json!("0x1"))]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_signs_and_verifies_nft_permits() {
+        use crate::deadline::FakeClock;
+        use crate::signing::address;
+
+        let domain = EIP712Domain::from_parts(
+            "AionRisingNFTs",
+            "1",
+            1u64,
+            "037eDa3aDB1198021A9b2e88C22B464fD38db3f3".parse().unwrap(),
+        );
+        let owner_key = test_key();
+        let owner = address(&owner_key);
+        let permit = NftPermit {
+            spender: Address::from(&[0x22; 20][..]),
+            token_id: 7.into(),
+            nonce: 0.into(),
+            deadline: 2_000.into(),
+        };
+
+        assert_eq!(
+            NftPermit::encode_type(),
+            "Permit(address spender,uint256 tokenId,uint256 nonce,uint256 deadline)"
+        );
+        let typed_data = serde_json::from_value::<EIP712>(permit.typed_data(&domain)).unwrap();
+        assert_eq!(
+            hash_structured_data(typed_data).unwrap(),
+            (&domain, &permit).digest().unwrap()
+        );
+
+        let signature = sign_nft_permit(&domain, &permit, &owner_key).unwrap();
+        assert_eq!(
+            verify_nft_permit(
+                &domain,
+                &permit,
+                &signature,
+                &[owner],
+                &FakeClock::new(2_000)
+            )
+            .unwrap(),
+            owner
+        );
+        assert_eq!(
+            verify_nft_permit(
+                &domain,
+                &permit,
+                &signature,
+                &[owner],
+                &FakeClock::new(2_001)
+            )
+            .unwrap_err()
+            .kind(),
+            ErrorKind::DeadlineExpired("deadline".into(), 2_000)
+        );
+
+        let mut transferred = permit.clone();
+        transferred.nonce = 1.into();
+        assert!(verify_nft_permit(
+            &domain,
+            &transferred,
+            &signature,
+            &[owner],
+            &FakeClock::new(1_000)
+        )
+        .is_err());
+    }
 }
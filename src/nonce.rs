@@ -0,0 +1,432 @@
+//! Nonce allocation and replay protection
+use crate::eip712::EIP712Domain;
+use crate::error::{ErrorKind, Result};
+use ethereum_types::{Address, H256, U256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// The (signer, recipient, domain) triple nonces are scoped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NonceScope {
+    pub signer: Address,
+    pub recipient: Address,
+    /// the domain separator
+    pub domain: H256,
+}
+
+impl NonceScope {
+    pub fn new(signer: Address, recipient: Address, domain: &EIP712Domain) -> Self {
+        NonceScope {
+            signer,
+            recipient,
+            domain: domain.separator(),
+        }
+    }
+}
+
+/// How new nonces are picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceStrategy {
+    /// 0, 1, 2, ... per scope, as expected by contracts that store a counter
+    Sequential,
+    /// uniformly random 256-bit nonces, as expected by contracts that store a
+    /// bitmap or set of used nonces
+    Random,
+}
+
+/// Hands out nonces and remembers which ones were used
+pub trait NonceManager {
+    /// a nonce that was never allocated for `scope` before
+    fn allocate(&mut self, scope: &NonceScope) -> Result<U256>;
+
+    /// marks `nonce` as used, failing if it already was
+    fn consume(&mut self, scope: &NonceScope, nonce: U256) -> Result<()>;
+
+    fn is_consumed(&self, scope: &NonceScope, nonce: U256) -> bool;
+}
+
+impl<N: NonceManager + ?Sized> NonceManager for &mut N {
+    fn allocate(&mut self, scope: &NonceScope) -> Result<U256> {
+        (**self).allocate(scope)
+    }
+
+    fn consume(&mut self, scope: &NonceScope, nonce: U256) -> Result<()> {
+        (**self).consume(scope, nonce)
+    }
+
+    fn is_consumed(&self, scope: &NonceScope, nonce: U256) -> bool {
+        (**self).is_consumed(scope, nonce)
+    }
+}
+
+/// Keeps nonces in memory, they are lost when the process exits
+#[derive(Debug, Clone)]
+pub struct InMemoryNonceManager {
+    strategy: NonceStrategy,
+    next: HashMap<NonceScope, U256>,
+    allocated: HashSet<(NonceScope, U256)>,
+    consumed: HashSet<(NonceScope, U256)>,
+}
+
+impl InMemoryNonceManager {
+    pub fn new(strategy: NonceStrategy) -> Self {
+        InMemoryNonceManager {
+            strategy,
+            next: HashMap::new(),
+            allocated: HashSet::new(),
+            consumed: HashSet::new(),
+        }
+    }
+
+    /// the nonce `allocate` hands out next, without recording it
+    fn fresh_nonce(&self, scope: &NonceScope) -> Result<U256> {
+        match self.strategy {
+            NonceStrategy::Sequential => {
+                let mut nonce = self.next.get(scope).cloned().unwrap_or_default();
+                while nonce != U256::max_value() && self.is_consumed(scope, nonce) {
+                    nonce = nonce.overflowing_add(U256::one()).0;
+                }
+                if nonce == U256::max_value() {
+                    Err(ErrorKind::NonceStorage(
+                        "sequential nonces exhausted".into(),
+                    ))?;
+                }
+                Ok(nonce)
+            }
+            NonceStrategy::Random => loop {
+                let nonce = U256::from(&rand::random::<[u8; 32]>()[..]);
+                if !self.allocated.contains(&(*scope, nonce)) && !self.is_consumed(scope, nonce) {
+                    return Ok(nonce);
+                }
+            },
+        }
+    }
+
+    /// records an allocation, keeping sequential nonces above every nonce seen
+    fn record_allocation(&mut self, scope: &NonceScope, nonce: U256) {
+        if self.strategy == NonceStrategy::Sequential {
+            let next = self.next.entry(*scope).or_default();
+            let (after, overflow) = nonce.overflowing_add(1.into());
+            if nonce >= *next && !overflow {
+                *next = after;
+            }
+        } else {
+            self.allocated.insert((*scope, nonce));
+        }
+    }
+}
+
+impl NonceManager for InMemoryNonceManager {
+    fn allocate(&mut self, scope: &NonceScope) -> Result<U256> {
+        let nonce = self.fresh_nonce(scope)?;
+        self.record_allocation(scope, nonce);
+        Ok(nonce)
+    }
+
+    fn consume(&mut self, scope: &NonceScope, nonce: U256) -> Result<()> {
+        if !self.consumed.insert((*scope, nonce)) {
+            Err(ErrorKind::NonceAlreadyUsed(format!("{}", nonce)))?;
+        }
+        Ok(())
+    }
+
+    fn is_consumed(&self, scope: &NonceScope, nonce: U256) -> bool {
+        self.consumed.contains(&(*scope, nonce))
+    }
+}
+
+/// Persists every allocation and consumption to an append-only log file, which
+/// is replayed when the manager is opened again. The file is locked while the
+/// manager is alive, so only one manager can use a log at a time.
+#[derive(Debug)]
+pub struct FileNonceManager {
+    state: InMemoryNonceManager,
+    log: File,
+    /// the length of the log up to its last complete entry
+    end: u64,
+}
+
+impl FileNonceManager {
+    /// opens or creates the log at `path` and locks it, failing if another
+    /// manager, in this or another process, holds it. A last entry that was
+    /// torn by a crash is dropped, it was never acknowledged.
+    pub fn open<P: AsRef<Path>>(path: P, strategy: NonceStrategy) -> Result<Self> {
+        let mut state = InMemoryNonceManager::new(strategy);
+        let log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(storage_error)?;
+        log.try_lock().map_err(|err| match err {
+            TryLockError::WouldBlock => {
+                ErrorKind::NonceStorage("the log is locked by another manager".into())
+            }
+            TryLockError::Error(err) => storage_error(err),
+        })?;
+
+        let mut bytes = vec![];
+        (&log).read_to_end(&mut bytes).map_err(storage_error)?;
+        // every entry ends with a newline, anything after the last one is torn
+        let complete = bytes
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |end| end + 1);
+        if complete < bytes.len() {
+            log.set_len(complete as u64).map_err(storage_error)?;
+        }
+        let entries = std::str::from_utf8(&bytes[..complete])
+            .map_err(|_| ErrorKind::NonceStorage("the log isn't valid UTF-8".into()))?;
+
+        for line in entries.lines() {
+            let (consumed, scope, nonce) = parse_entry(line)?;
+            if consumed {
+                state.consumed.insert((scope, nonce));
+            } else {
+                state.record_allocation(&scope, nonce);
+            }
+        }
+
+        Ok(FileNonceManager {
+            state,
+            log,
+            end: complete as u64,
+        })
+    }
+
+    fn append(&mut self, kind: char, scope: &NonceScope, nonce: U256) -> Result<()> {
+        // a failed write may have left part of an entry behind, which would
+        // end up in the middle of the log once the next entry follows it
+        if self.log.metadata().map_err(storage_error)?.len() != self.end {
+            self.log.set_len(self.end).map_err(storage_error)?;
+        }
+        let entry = format!(
+            "{} 0x{:x} 0x{:x} 0x{:x} 0x{:x}\n",
+            kind, scope.signer, scope.recipient, scope.domain, nonce
+        );
+        let written = self
+            .log
+            .write_all(entry.as_bytes())
+            .and_then(|_| self.log.sync_data());
+        match written {
+            Ok(()) => {
+                self.end += entry.len() as u64;
+                Ok(())
+            }
+            Err(err) => {
+                // retried before the next entry if it fails
+                let _ = self.log.set_len(self.end);
+                Err(storage_error(err))?
+            }
+        }
+    }
+}
+
+impl NonceManager for FileNonceManager {
+    // the log is written first, so memory never holds an entry the log lost
+    fn allocate(&mut self, scope: &NonceScope) -> Result<U256> {
+        let nonce = self.state.fresh_nonce(scope)?;
+        self.append('a', scope, nonce)?;
+        self.state.record_allocation(scope, nonce);
+        Ok(nonce)
+    }
+
+    fn consume(&mut self, scope: &NonceScope, nonce: U256) -> Result<()> {
+        if self.state.is_consumed(scope, nonce) {
+            Err(ErrorKind::NonceAlreadyUsed(format!("{}", nonce)))?;
+        }
+        self.append('c', scope, nonce)?;
+        self.state.consume(scope, nonce)
+    }
+
+    fn is_consumed(&self, scope: &NonceScope, nonce: U256) -> bool {
+        self.state.is_consumed(scope, nonce)
+    }
+}
+
+fn storage_error(err: std::io::Error) -> ErrorKind {
+    ErrorKind::NonceStorage(format!("{}", err))
+}
+
+/// `<a|c> <signer> <recipient> <domain> <nonce>`
+fn parse_entry(line: &str) -> Result<(bool, NonceScope, U256)> {
+    let invalid = || ErrorKind::NonceStorage(format!("invalid log entry '{}'", line));
+    let parts = line.split(' ').collect::<Vec<_>>();
+    let hex = |index: usize| {
+        parts
+            .get(index)
+            .and_then(|part| part.strip_prefix("0x"))
+            .ok_or_else(invalid)
+    };
+    let consumed = match parts.first() {
+        Some(&"a") => false,
+        Some(&"c") => true,
+        _ => Err(invalid())?,
+    };
+    if parts.len() != 5 {
+        Err(invalid())?;
+    }
+
+    let scope = NonceScope {
+        signer: hex(1)?.parse().map_err(|_| invalid())?,
+        recipient: hex(2)?.parse().map_err(|_| invalid())?,
+        domain: hex(3)?.parse().map_err(|_| invalid())?,
+    };
+    let nonce = hex(4)?;
+    // longer strings don't fit into a U256
+    if nonce.len() > 64 {
+        Err(invalid())?;
+    }
+    let nonce = nonce.parse().map_err(|_| invalid())?;
+    Ok((consumed, scope, nonce))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(recipient: u8) -> NonceScope {
+        NonceScope {
+            signer: Address::from(&[1; 20][..]),
+            recipient: Address::from(&[recipient; 20][..]),
+            domain: H256::from(&[2; 32][..]),
+        }
+    }
+
+    #[test]
+    fn it_allocates_sequential_nonces_per_scope() {
+        let mut nonces = InMemoryNonceManager::new(NonceStrategy::Sequential);
+
+        assert_eq!(nonces.allocate(&scope(1)).unwrap(), 0.into());
+        assert_eq!(nonces.allocate(&scope(1)).unwrap(), 1.into());
+        assert_eq!(nonces.allocate(&scope(2)).unwrap(), 0.into());
+
+        // nonces consumed elsewhere are skipped
+        nonces.consume(&scope(1), 2.into()).unwrap();
+        assert_eq!(nonces.allocate(&scope(1)).unwrap(), 3.into());
+    }
+
+    #[test]
+    fn it_allocates_distinct_random_nonces() {
+        let mut nonces = InMemoryNonceManager::new(NonceStrategy::Random);
+        let allocated = (0..100)
+            .map(|_| nonces.allocate(&scope(1)).unwrap())
+            .collect::<HashSet<_>>();
+
+        assert_eq!(allocated.len(), 100);
+        assert!(allocated.iter().any(|nonce| nonce.bits() > 128));
+    }
+
+    #[test]
+    fn it_rejects_replays() {
+        let mut nonces = InMemoryNonceManager::new(NonceStrategy::Sequential);
+        nonces.consume(&scope(1), 5.into()).unwrap();
+
+        assert!(nonces.is_consumed(&scope(1), 5.into()));
+        assert!(!nonces.is_consumed(&scope(2), 5.into()));
+        assert_eq!(
+            nonces.consume(&scope(1), 5.into()).unwrap_err().kind(),
+            ErrorKind::NonceAlreadyUsed("5".into())
+        );
+        nonces.consume(&scope(2), 5.into()).unwrap();
+    }
+
+    #[test]
+    fn it_persists_nonces_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nonces.log");
+
+        {
+            let mut nonces = FileNonceManager::open(&path, NonceStrategy::Sequential).unwrap();
+            assert_eq!(nonces.allocate(&scope(1)).unwrap(), 0.into());
+            assert_eq!(nonces.allocate(&scope(1)).unwrap(), 1.into());
+            nonces.consume(&scope(1), 0.into()).unwrap();
+        }
+
+        let mut nonces = FileNonceManager::open(&path, NonceStrategy::Sequential).unwrap();
+        assert_eq!(nonces.allocate(&scope(1)).unwrap(), 2.into());
+        assert!(nonces.is_consumed(&scope(1), 0.into()));
+        assert!(nonces.consume(&scope(1), 0.into()).is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn it_leaves_memory_untouched_when_the_log_fails() {
+        // writes to /dev/full fail with ENOSPC
+        let mut nonces = FileNonceManager {
+            state: InMemoryNonceManager::new(NonceStrategy::Sequential),
+            log: OpenOptions::new().append(true).open("/dev/full").unwrap(),
+            end: 0,
+        };
+
+        assert!(nonces.consume(&scope(1), 0.into()).is_err());
+        assert!(!nonces.is_consumed(&scope(1), 0.into()));
+        assert!(nonces.allocate(&scope(1)).is_err());
+        assert_eq!(nonces.state.fresh_nonce(&scope(1)).unwrap(), 0.into());
+    }
+
+    #[test]
+    fn it_locks_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nonces.log");
+
+        let nonces = FileNonceManager::open(&path, NonceStrategy::Sequential).unwrap();
+        assert!(FileNonceManager::open(&path, NonceStrategy::Sequential).is_err());
+        drop(nonces);
+        FileNonceManager::open(&path, NonceStrategy::Sequential).unwrap();
+    }
+
+    #[test]
+    fn it_drops_a_torn_last_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nonces.log");
+        {
+            let mut nonces = FileNonceManager::open(&path, NonceStrategy::Sequential).unwrap();
+            nonces.allocate(&scope(1)).unwrap();
+        }
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, format!("{}c 0x0101", log)).unwrap();
+
+        let mut nonces = FileNonceManager::open(&path, NonceStrategy::Sequential).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), log);
+        assert_eq!(nonces.allocate(&scope(1)).unwrap(), 1.into());
+        drop(nonces);
+        let nonces = FileNonceManager::open(&path, NonceStrategy::Sequential).unwrap();
+        assert!(!nonces.is_consumed(&scope(1), 0.into()));
+    }
+
+    #[test]
+    fn it_drops_a_torn_entry_before_appending() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nonces.log");
+        let mut nonces = FileNonceManager::open(&path, NonceStrategy::Sequential).unwrap();
+        nonces.allocate(&scope(1)).unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+
+        // what a write that failed halfway leaves behind
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"c 0x0101")
+            .unwrap();
+        nonces.allocate(&scope(1)).unwrap();
+        drop(nonces);
+
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with(&log));
+        let mut nonces = FileNonceManager::open(&path, NonceStrategy::Sequential).unwrap();
+        assert!(!nonces.is_consumed(&scope(1), 0.into()));
+        assert_eq!(nonces.allocate(&scope(1)).unwrap(), 2.into());
+    }
+
+    #[test]
+    fn it_rejects_corrupt_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nonces.log");
+        std::fs::write(&path, "a 0x01 garbage\n").unwrap();
+
+        assert!(FileNonceManager::open(&path, NonceStrategy::Random).is_err());
+    }
+}
@@ -7,8 +7,10 @@ use toolshed::Arena;
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Address,
-    Uint,
-    Int,
+    /// bit width, e.g. 256 for `uint256`
+    Uint(u16),
+    /// bit width, e.g. 256 for `int256`
+    Int(u16),
     String,
     Bool,
     Bytes,
@@ -24,8 +26,8 @@ impl From<Type> for String {
     fn from(field_type: Type) -> String {
         match field_type {
             Type::Address => "address".into(),
-            Type::Uint => "uint".into(),
-            Type::Int => "int".into(),
+            Type::Uint(bits) => format!("uint{}", bits),
+            Type::Int(bits) => format!("int{}", bits),
             Type::String => "string".into(),
             Type::Bool => "bool".into(),
             Type::Bytes => "bytes".into(),
@@ -80,8 +82,8 @@ impl Parser {
                 Token::TypeByte => Type::Byte(lexer.type_size.0),
                 Token::TypeBytes => Type::Bytes,
                 Token::TypeBool => Type::Bool,
-                Token::TypeUint => Type::Uint,
-                Token::TypeInt => Type::Int,
+                Token::TypeUint => Type::Uint(lexer.type_size.0 as u16 * 8),
+                Token::TypeInt => Type::Int(lexer.type_size.0 as u16 * 8),
                 Token::TypeString => Type::String,
                 Token::TypeAddress => Type::Address,
                 Token::LiteralInteger => {
@@ -99,23 +101,18 @@ impl Parser {
                     lexer.consume();
                     continue;
                 }
-                Token::BracketClose if array_depth < 10 => {
-                    if state == State::Open && token.is_some() {
-                        let length = current_array_length.take();
-                        state = State::Close;
-                        token = Some(Type::Array {
-                            inner: Box::new(token.expect("if statement checks for some; qed")),
-                            length,
-                        });
-                        lexer.consume();
-                        array_depth += 1;
-                        continue;
-                    } else {
-                        return Err(ErrorKind::UnexpectedToken(
-                            lexer.token_as_str().to_owned(),
-                            field_type.to_owned(),
-                        ))?;
-                    }
+                Token::BracketClose
+                    if array_depth < 10 && state == State::Open && token.is_some() =>
+                {
+                    let length = current_array_length.take();
+                    state = State::Close;
+                    token = Some(Type::Array {
+                        inner: Box::new(token.expect("if statement checks for some; qed")),
+                        length,
+                    });
+                    lexer.consume();
+                    array_depth += 1;
+                    continue;
                 }
                 Token::BracketClose if array_depth == 10 => {
                     return Err(ErrorKind::UnsupportedArrayDepth)?
@@ -132,6 +129,6 @@ impl Parser {
             lexer.consume();
         }
 
-        Ok(token.ok_or_else(|| ErrorKind::NonExistentType)?)
+        Ok(token.ok_or(ErrorKind::NonExistentType)?)
     }
 }
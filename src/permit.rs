@@ -0,0 +1,326 @@
+//! ERC-20 permits: EIP-2612 and DAI's `allowed`/`expiry` variant
+use crate::deadline::{check_deadline, Clock};
+use crate::eip712::{hex, EIP712Domain, FieldType, MessageTypes, EIP712};
+use crate::encode::hash_structured_data;
+use crate::error::{ErrorKind, Result};
+use crate::nft_helpers::{generate_typed_data, MessageSchema};
+use crate::signing::{recover, sign, Signature};
+use crate::typed::{Eip712Struct, Eip712Type};
+use ethereum_types::{Address, H256, U256};
+use secp256k1::SecretKey;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// `Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)`
+/// as defined by EIP-2612
+#[derive(Debug, Clone, PartialEq)]
+pub struct Permit {
+    pub owner: Address,
+    pub spender: Address,
+    pub value: U256,
+    pub nonce: U256,
+    pub deadline: U256,
+}
+
+impl Eip712Struct for Permit {
+    fn type_name() -> String {
+        "Permit".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("owner", "address"),
+            FieldType::new("spender", "address"),
+            FieldType::new("value", "uint256"),
+            FieldType::new("nonce", "uint256"),
+            FieldType::new("deadline", "uint256"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.owner.encode_word(),
+            self.spender.encode_word(),
+            self.value.encode_word(),
+            self.nonce.encode_word(),
+            self.deadline.encode_word(),
+        ]
+    }
+}
+
+/// `Permit(address holder,address spender,uint256 nonce,uint256 expiry,bool allowed)`
+/// as used by DAI, which approves an unlimited amount or revokes the approval
+#[derive(Debug, Clone, PartialEq)]
+pub struct DaiPermit {
+    pub holder: Address,
+    pub spender: Address,
+    pub nonce: U256,
+    /// 0 never expires
+    pub expiry: U256,
+    pub allowed: bool,
+}
+
+impl Eip712Struct for DaiPermit {
+    fn type_name() -> String {
+        "Permit".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("holder", "address"),
+            FieldType::new("spender", "address"),
+            FieldType::new("nonce", "uint256"),
+            FieldType::new("expiry", "uint256"),
+            FieldType::new("allowed", "bool"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.holder.encode_word(),
+            self.spender.encode_word(),
+            self.nonce.encode_word(),
+            self.expiry.encode_word(),
+            self.allowed.encode_word(),
+        ]
+    }
+}
+
+/// A permit message, signed by the token owner
+pub trait PermitMessage: Eip712Struct {
+    /// the address that has to sign the permit
+    fn owner(&self) -> Address;
+
+    /// fails if the permit expired more than `skew` before `now`
+    fn check_expiry(&self, now: u64, skew: Duration) -> Result<()>;
+
+    /// the message as JSON
+    fn message(&self) -> Value;
+
+    /// the typed data to hash with `hash_structured_data`
+    fn typed_data(&self, domain: &EIP712Domain) -> EIP712 {
+        EIP712::builder()
+            .typed_domain(domain.clone())
+            .custom_field((Self::type_name(), Self::fields()))
+            .message(self.message())
+            .build()
+    }
+
+    /// the typed data JSON to pass to `eth_signTypedData_v4`
+    fn typed_data_json(&self, domain: &EIP712Domain) -> Value {
+        let schema = MessageSchema::new(&Self::type_name(), Self::fields());
+        generate_typed_data(domain, &schema, self.message())
+            .expect("permits always match their schema; qed")
+    }
+}
+
+impl PermitMessage for Permit {
+    fn owner(&self) -> Address {
+        self.owner
+    }
+
+    fn check_expiry(&self, now: u64, skew: Duration) -> Result<()> {
+        check_deadline("deadline", self.deadline, now, skew)
+    }
+
+    fn message(&self) -> Value {
+        json!({
+            "owner": hex(self.owner),
+            "spender": hex(self.spender),
+            "value": hex(self.value),
+            "nonce": hex(self.nonce),
+            "deadline": hex(self.deadline),
+        })
+    }
+}
+
+impl PermitMessage for DaiPermit {
+    fn owner(&self) -> Address {
+        self.holder
+    }
+
+    fn check_expiry(&self, now: u64, skew: Duration) -> Result<()> {
+        if self.expiry.is_zero() {
+            return Ok(());
+        }
+        check_deadline("expiry", self.expiry, now, skew)
+    }
+
+    fn message(&self) -> Value {
+        json!({
+            "holder": hex(self.holder),
+            "spender": hex(self.spender),
+            "nonce": hex(self.nonce),
+            "expiry": hex(self.expiry),
+            "allowed": self.allowed,
+        })
+    }
+}
+
+/// Signs `permit` for the token at `domain`; the signature's `v`, `r` and `s`
+/// are the last arguments of the token's `permit()`
+pub fn sign_permit<P: PermitMessage>(
+    domain: &EIP712Domain,
+    permit: &P,
+    secret_key: &SecretKey,
+) -> Result<Signature> {
+    let digest = hash_structured_data(permit.typed_data(domain))?;
+    Ok(sign(&digest, secret_key))
+}
+
+/// Checks that `permit` hasn't expired according to `clock` and was signed by
+/// its owner, like the token's `permit()` does before touching the nonce
+pub fn verify_permit<P: PermitMessage, C: Clock>(
+    domain: &EIP712Domain,
+    permit: &P,
+    signature: &Signature,
+    clock: &C,
+) -> Result<()> {
+    permit.check_expiry(clock.now(), clock.skew())?;
+    let signer = recover(&hash_structured_data(permit.typed_data(domain))?, signature)?;
+    if signer != permit.owner() {
+        Err(ErrorKind::UnauthorizedSigner(format!("0x{:x}", signer)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deadline::FakeClock;
+    use crate::signing::{address, test_key};
+
+    fn token() -> EIP712Domain {
+        EIP712Domain::from_parts(
+            "Test Token",
+            "1",
+            1u64,
+            "CcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC".parse().unwrap(),
+        )
+    }
+
+    fn spender() -> Address {
+        Address::from(&[0x22; 20][..])
+    }
+
+    fn permit() -> Permit {
+        Permit {
+            owner: address(&test_key()),
+            spender: spender(),
+            value: U256::from(10).pow(18.into()),
+            nonce: 0.into(),
+            deadline: 1_700_000_000.into(),
+        }
+    }
+
+    #[test]
+    fn it_hashes_permits_like_typed_structs() {
+        let permit = permit();
+
+        assert_eq!(
+            Permit::encode_type(),
+            "Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)"
+        );
+        // PERMIT_TYPEHASH of OpenZeppelin's ERC20Permit
+        assert_eq!(
+            format!("{:x}", Permit::type_hash()),
+            "6e71edae12b1b97f4d1f60370fef10105fa2faae0126114a169c64845d6126c9"
+        );
+        assert_eq!(
+            hash_structured_data(permit.typed_data(&token())).unwrap(),
+            hash_structured_data((&token(), &permit)).unwrap()
+        );
+
+        let json = permit.typed_data_json(&token());
+        assert_eq!(json["primaryType"], "Permit");
+        assert_eq!(json["message"]["value"], "0xde0b6b3a7640000");
+        let parsed = serde_json::from_value::<EIP712>(json).unwrap();
+        assert_eq!(
+            hash_structured_data(parsed).unwrap(),
+            hash_structured_data((&token(), &permit)).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_signs_and_verifies_permits() {
+        let permit = permit();
+        let signature = sign_permit(&token(), &permit, &test_key()).unwrap();
+        let clock = FakeClock::new(1_700_000_000);
+
+        assert!(signature.v == 27 || signature.v == 28);
+        verify_permit(&token(), &permit, &signature, &clock).unwrap();
+
+        let mut raised = permit.clone();
+        raised.value += 1.into();
+        assert!(verify_permit(&token(), &raised, &signature, &clock).is_err());
+
+        let other_chain =
+            EIP712Domain::from_parts("Test Token", "1", 5u64, token().verifying_contract());
+        assert!(verify_permit(&other_chain, &permit, &signature, &clock).is_err());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            verify_permit(&token(), &permit, &signature, &clock)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::DeadlineExpired("deadline".into(), 1_700_000_000)
+        );
+    }
+
+    #[test]
+    fn it_supports_dai_permits() {
+        let dai = EIP712Domain::from_parts(
+            "Dai Stablecoin",
+            "1",
+            1u64,
+            "6B175474E89094C44Da98b954EedeAC495271d0F".parse().unwrap(),
+        );
+        let permit = DaiPermit {
+            holder: address(&test_key()),
+            spender: spender(),
+            nonce: 3.into(),
+            expiry: 0.into(),
+            allowed: true,
+        };
+
+        assert_eq!(
+            DaiPermit::encode_type(),
+            "Permit(address holder,address spender,uint256 nonce,uint256 expiry,bool allowed)"
+        );
+        // DAI's PERMIT_TYPEHASH and its DOMAIN_SEPARATOR() on mainnet
+        assert_eq!(
+            format!("{:x}", DaiPermit::type_hash()),
+            "ea2aa0a1be11a07ed86d755c93467f4f82362b452371d1ba94d1715123511acb"
+        );
+        assert_eq!(
+            format!("{:x}", dai.separator()),
+            "dbb8cf42e1ecb028be3f3dbc922e1d878b963f411dc388ced501601c60f7c6f7"
+        );
+        assert_eq!(
+            hash_structured_data(permit.typed_data(&dai)).unwrap(),
+            hash_structured_data((&dai, &permit)).unwrap()
+        );
+
+        let signature = sign_permit(&dai, &permit, &test_key()).unwrap();
+        // an expiry of 0 never expires
+        verify_permit(&dai, &permit, &signature, &FakeClock::new(u64::MAX)).unwrap();
+
+        let revoked = DaiPermit {
+            allowed: false,
+            ..permit.clone()
+        };
+        assert!(verify_permit(&dai, &revoked, &signature, &FakeClock::new(0)).is_err());
+
+        let expiring = DaiPermit {
+            expiry: 100.into(),
+            ..permit
+        };
+        let signature = sign_permit(&dai, &expiring, &test_key()).unwrap();
+        verify_permit(&dai, &expiring, &signature, &FakeClock::new(100)).unwrap();
+        assert!(verify_permit(&dai, &expiring, &signature, &FakeClock::new(101)).is_err());
+    }
+}
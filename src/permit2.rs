@@ -0,0 +1,665 @@
+//! Uniswap Permit2: allowance permits, signature transfers and witnesses
+use crate::eip712::{EIP712Domain, FieldType, MessageTypes};
+use crate::encode::{encode_type, hash_struct_value};
+use crate::error::{ErrorKind, Result};
+use crate::parser::{Parser, Type};
+use crate::typed::{Eip712Struct, Eip712Type, StructuredData, Uint160, Uint48};
+use crate::value::Eip712Value;
+use ethereum_types::{Address, H256, U256};
+
+/// the address Permit2 is deployed at on every chain
+pub const PERMIT2_ADDRESS: &str = "000000000022D473030F116dDEE9F6B43aC78BA3";
+
+/// the `EIP712Domain(string name,uint256 chainId,address verifyingContract)`
+/// of Permit2 on `chain_id`, it has no `version`
+pub fn permit2_domain<C: Into<U256>>(chain_id: C) -> EIP712Domain {
+    EIP712Domain::unversioned(
+        "Permit2",
+        chain_id,
+        PERMIT2_ADDRESS
+            .parse()
+            .expect("the address is valid hex; qed"),
+    )
+}
+
+fn uint(value: U256) -> Eip712Value {
+    Eip712Value::Uint(256, value)
+}
+
+/// `PermitDetails(address token,uint160 amount,uint48 expiration,uint48 nonce)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermitDetails {
+    pub token: Address,
+    pub amount: Uint160,
+    pub expiration: Uint48,
+    pub nonce: Uint48,
+}
+
+impl Eip712Struct for PermitDetails {
+    fn type_name() -> String {
+        "PermitDetails".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("token", "address"),
+            FieldType::new("amount", "uint160"),
+            FieldType::new("expiration", "uint48"),
+            FieldType::new("nonce", "uint48"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.token.encode_word(),
+            self.amount.encode_word(),
+            self.expiration.encode_word(),
+            self.nonce.encode_word(),
+        ]
+    }
+}
+
+/// `PermitSingle(PermitDetails details,address spender,uint256 sigDeadline)`,
+/// sets the allowance of one token
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermitSingle {
+    pub details: PermitDetails,
+    pub spender: Address,
+    pub sig_deadline: U256,
+}
+
+impl Eip712Struct for PermitSingle {
+    fn type_name() -> String {
+        "PermitSingle".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("details", &PermitDetails::field_type()),
+            FieldType::new("spender", "address"),
+            FieldType::new("sigDeadline", "uint256"),
+        ]
+    }
+
+    fn add_dependencies(types: &mut MessageTypes) {
+        PermitDetails::add_field_types(types);
+    }
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.details.encode_word(),
+            self.spender.encode_word(),
+            self.sig_deadline.encode_word(),
+        ]
+    }
+}
+
+/// `PermitBatch(PermitDetails[] details,address spender,uint256 sigDeadline)`,
+/// sets the allowances of several tokens
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermitBatch {
+    pub details: Vec<PermitDetails>,
+    pub spender: Address,
+    pub sig_deadline: U256,
+}
+
+impl Eip712Struct for PermitBatch {
+    fn type_name() -> String {
+        "PermitBatch".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("details", &Vec::<PermitDetails>::field_type()),
+            FieldType::new("spender", "address"),
+            FieldType::new("sigDeadline", "uint256"),
+        ]
+    }
+
+    fn add_dependencies(types: &mut MessageTypes) {
+        PermitDetails::add_field_types(types);
+    }
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.details.encode_word(),
+            self.spender.encode_word(),
+            self.sig_deadline.encode_word(),
+        ]
+    }
+}
+
+/// `TokenPermissions(address token,uint256 amount)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenPermissions {
+    pub token: Address,
+    pub amount: U256,
+}
+
+impl TokenPermissions {
+    fn to_value(&self) -> Eip712Value {
+        Eip712Value::new_struct(
+            "TokenPermissions",
+            vec![
+                ("token", Eip712Value::Address(self.token)),
+                ("amount", uint(self.amount)),
+            ],
+        )
+    }
+}
+
+impl Eip712Struct for TokenPermissions {
+    fn type_name() -> String {
+        "TokenPermissions".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("token", "address"),
+            FieldType::new("amount", "uint256"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![self.token.encode_word(), self.amount.encode_word()]
+    }
+}
+
+/// `PermitTransferFrom(TokenPermissions permitted,address spender,uint256 nonce,uint256 deadline)`,
+/// a one-off transfer of one token
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermitTransferFrom {
+    pub permitted: TokenPermissions,
+    pub spender: Address,
+    pub nonce: U256,
+    pub deadline: U256,
+}
+
+impl Eip712Struct for PermitTransferFrom {
+    fn type_name() -> String {
+        "PermitTransferFrom".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("permitted", &TokenPermissions::field_type()),
+            FieldType::new("spender", "address"),
+            FieldType::new("nonce", "uint256"),
+            FieldType::new("deadline", "uint256"),
+        ]
+    }
+
+    fn add_dependencies(types: &mut MessageTypes) {
+        TokenPermissions::add_field_types(types);
+    }
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.permitted.encode_word(),
+            self.spender.encode_word(),
+            self.nonce.encode_word(),
+            self.deadline.encode_word(),
+        ]
+    }
+}
+
+/// `PermitBatchTransferFrom(TokenPermissions[] permitted,address spender,uint256 nonce,uint256 deadline)`,
+/// a one-off transfer of several tokens
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermitBatchTransferFrom {
+    pub permitted: Vec<TokenPermissions>,
+    pub spender: Address,
+    pub nonce: U256,
+    pub deadline: U256,
+}
+
+impl Eip712Struct for PermitBatchTransferFrom {
+    fn type_name() -> String {
+        "PermitBatchTransferFrom".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("permitted", &Vec::<TokenPermissions>::field_type()),
+            FieldType::new("spender", "address"),
+            FieldType::new("nonce", "uint256"),
+            FieldType::new("deadline", "uint256"),
+        ]
+    }
+
+    fn add_dependencies(types: &mut MessageTypes) {
+        TokenPermissions::add_field_types(types);
+    }
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.permitted.encode_word(),
+            self.spender.encode_word(),
+            self.nonce.encode_word(),
+            self.deadline.encode_word(),
+        ]
+    }
+}
+
+/// A signature transfer that can carry a witness
+pub trait TransferPermit: Eip712Struct {
+    /// the primary type when signed with a witness, e.g. `PermitWitnessTransferFrom`
+    fn witness_type_name() -> String;
+
+    /// the permit as a typed value
+    fn to_value(&self) -> Eip712Value;
+}
+
+impl TransferPermit for PermitTransferFrom {
+    fn witness_type_name() -> String {
+        "PermitWitnessTransferFrom".into()
+    }
+
+    fn to_value(&self) -> Eip712Value {
+        Eip712Value::new_struct(
+            "PermitTransferFrom",
+            vec![
+                ("permitted", self.permitted.to_value()),
+                ("spender", Eip712Value::Address(self.spender)),
+                ("nonce", uint(self.nonce)),
+                ("deadline", uint(self.deadline)),
+            ],
+        )
+    }
+}
+
+impl TransferPermit for PermitBatchTransferFrom {
+    fn witness_type_name() -> String {
+        "PermitBatchWitnessTransferFrom".into()
+    }
+
+    fn to_value(&self) -> Eip712Value {
+        Eip712Value::new_struct(
+            "PermitBatchTransferFrom",
+            vec![
+                (
+                    "permitted",
+                    Eip712Value::Array(self.permitted.iter().map(|p| p.to_value()).collect()),
+                ),
+                ("spender", Eip712Value::Address(self.spender)),
+                ("nonce", uint(self.nonce)),
+                ("deadline", uint(self.deadline)),
+            ],
+        )
+    }
+}
+
+/// Extra data the signer approves together with a transfer, e.g. an order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Witness {
+    /// the name of the witness field, `witness` by default
+    pub field_name: String,
+    /// a struct value
+    pub value: Eip712Value,
+    /// the value's struct type and the types it depends on
+    pub types: MessageTypes,
+}
+
+impl Witness {
+    pub fn new(value: Eip712Value, types: MessageTypes) -> Self {
+        Witness {
+            field_name: "witness".into(),
+            value,
+            types,
+        }
+    }
+
+    pub fn with_field_name(mut self, field_name: &str) -> Self {
+        self.field_name = field_name.to_owned();
+        self
+    }
+
+    fn type_name(&self) -> Result<&str> {
+        match &self.value {
+            Eip712Value::Struct(type_name, _) if self.types.contains_key(type_name) => {
+                Ok(type_name)
+            }
+            _ => Err(ErrorKind::NonExistentType)?,
+        }
+    }
+
+    /// the `bytes32 witness` argument of `permitWitnessTransferFrom`
+    pub fn hash(&self) -> Result<H256> {
+        hash_struct_value(
+            &Parser::new(),
+            &Type::Custom(self.type_name()?.to_owned()),
+            &self.types,
+            &self.value,
+        )
+    }
+}
+
+/// A transfer permit signed together with a witness
+#[derive(Debug, Clone, PartialEq)]
+pub struct PermitWitness<P> {
+    pub permit: P,
+    pub witness: Witness,
+}
+
+impl<P: TransferPermit> PermitWitness<P> {
+    pub fn new(permit: P, witness: Witness) -> Self {
+        PermitWitness { permit, witness }
+    }
+
+    /// the permit's types with the witness field appended to the primary type
+    /// and the witness types merged in
+    pub fn message_types(&self) -> Result<MessageTypes> {
+        let mut types = P::message_types();
+        types.remove(&P::type_name());
+        for (name, fields) in &self.witness.types {
+            match types.get(name) {
+                Some(existing) if existing != fields => {
+                    Err(ErrorKind::DuplicateType(name.clone()))?
+                }
+                _ => types.insert(name.clone(), fields.clone()),
+            };
+        }
+
+        let mut fields = P::fields();
+        if fields.iter().any(|f| f.name == self.witness.field_name) {
+            Err(ErrorKind::DuplicateType(self.witness.field_name.clone()))?;
+        }
+        fields.push(FieldType::new(
+            &self.witness.field_name,
+            self.witness.type_name()?,
+        ));
+        if types.insert(P::witness_type_name(), fields).is_some() {
+            Err(ErrorKind::DuplicateType(P::witness_type_name()))?;
+        }
+        Ok(types)
+    }
+
+    /// the message, with the witness as its last field
+    pub fn message(&self) -> Eip712Value {
+        let mut fields = match self.permit.to_value() {
+            Eip712Value::Struct(_, fields) => fields,
+            _ => unreachable!("permits are structs; qed"),
+        };
+        fields.insert(self.witness.field_name.clone(), self.witness.value.clone());
+        Eip712Value::Struct(P::witness_type_name(), fields)
+    }
+
+    /// the `witnessTypeString` argument of `permitWitnessTransferFrom`: the
+    /// encoded type after the permit's own fields, e.g.
+    /// `ExampleWitness witness)ExampleWitness(address user)TokenPermissions(address token,uint256 amount)`
+    pub fn witness_type_string(&self) -> Result<String> {
+        let encoded = encode_type(&P::witness_type_name(), &self.message_types()?)?;
+        let stub = format!(
+            "{}({},",
+            P::witness_type_name(),
+            P::fields()
+                .iter()
+                .map(|f| format!("{} {}", f.type_, f.name))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        Ok(encoded[stub.len()..].to_owned())
+    }
+
+    /// the EIP-712 digest the owner signs
+    pub fn digest(&self, domain: &EIP712Domain) -> Result<H256> {
+        (domain, &self.message_types()?, &self.message()).digest()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::hash_structured_data;
+    use crate::typed::Eip712Struct;
+    use keccak_hash::keccak;
+    use std::collections::HashMap;
+
+    fn token() -> Address {
+        "A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48".parse().unwrap()
+    }
+
+    fn spender() -> Address {
+        "3fC91A3afd70395Cd496C647d5a6CC9D4B2b7FAD".parse().unwrap()
+    }
+
+    fn transfer() -> PermitTransferFrom {
+        PermitTransferFrom {
+            permitted: TokenPermissions {
+                token: token(),
+                amount: 1_000_000.into(),
+            },
+            spender: spender(),
+            nonce: 0.into(),
+            deadline: 1_700_000_000.into(),
+        }
+    }
+
+    fn example_witness() -> Witness {
+        let mut types = HashMap::new();
+        types.insert(
+            "ExampleWitness".to_string(),
+            vec![FieldType::new("user", "address")],
+        );
+        Witness::new(
+            Eip712Value::new_struct(
+                "ExampleWitness",
+                vec![("user", Eip712Value::Address(Address::from(&[0x11; 20][..])))],
+            ),
+            types,
+        )
+    }
+
+    #[test]
+    fn it_uses_permit2_type_hashes() {
+        // the constants from Permit2's PermitHash library
+        assert_eq!(
+            format!("{:x}", TokenPermissions::type_hash()),
+            "618358ac3db8dc274f0cd8829da7e234bd48cd73c4a740aede1adec9846d06a1"
+        );
+        assert_eq!(
+            format!("{:x}", PermitTransferFrom::type_hash()),
+            "939c21a48a8dbe3a9a2404a1d46691e4d39f6583d6ec6b35714604c986d80106"
+        );
+        assert_eq!(
+            format!("{:x}", PermitSingle::type_hash()),
+            "f3841cd1ff0085026a6327b620b67997ce40f282c88a8e905a7a5626e310f3d0"
+        );
+        assert_eq!(
+            PermitSingle::encode_type(),
+            "PermitSingle(PermitDetails details,address spender,uint256 sigDeadline)\
+             PermitDetails(address token,uint160 amount,uint48 expiration,uint48 nonce)"
+        );
+        assert_eq!(
+            PermitBatchTransferFrom::encode_type(),
+            "PermitBatchTransferFrom(TokenPermissions[] permitted,address spender,uint256 nonce,uint256 deadline)\
+             TokenPermissions(address token,uint256 amount)"
+        );
+    }
+
+    #[test]
+    fn it_hashes_the_domain_without_a_version() {
+        let domain = permit2_domain(1u64);
+        let type_hash =
+            keccak("EIP712Domain(string name,uint256 chainId,address verifyingContract)");
+        let expected = keccak(
+            [
+                type_hash,
+                keccak("Permit2"),
+                U256::from(1).encode_word(),
+                domain.verifying_contract().encode_word(),
+            ]
+            .iter()
+            .flat_map(|word| word.0)
+            .collect::<Vec<_>>(),
+        );
+
+        assert_eq!(domain.version(), None);
+        assert_eq!(domain.separator(), expected);
+        // Permit2's DOMAIN_SEPARATOR() on mainnet
+        assert_eq!(
+            format!("{:x}", domain.separator()),
+            "866a5aba21966af95d6c7ab78eb2b2fc913915c28be3b9aa07cc04ff903e3f28"
+        );
+    }
+
+    #[test]
+    fn it_hashes_allowance_permits() {
+        let details = PermitDetails {
+            token: token(),
+            amount: Uint160::MAX,
+            expiration: Uint48::MAX,
+            nonce: 0.into(),
+        };
+        let single = PermitSingle {
+            details: details.clone(),
+            spender: spender(),
+            sig_deadline: 1_700_000_000.into(),
+        };
+        let batch = PermitBatch {
+            details: vec![details.clone(), details],
+            spender: spender(),
+            sig_deadline: 1_700_000_000.into(),
+        };
+
+        let domain = permit2_domain(1u64);
+        let json = serde_json::json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" }
+                ],
+                "PermitSingle": PermitSingle::fields(),
+                "PermitDetails": PermitDetails::fields(),
+            },
+            "primaryType": "PermitSingle",
+            "domain": domain,
+            "message": {
+                "details": {
+                    "token": "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+                    "amount": "0xffffffffffffffffffffffffffffffffffffffff",
+                    "expiration": "0xffffffffffff",
+                    "nonce": "0x0"
+                },
+                "spender": "0x3fC91A3afd70395Cd496C647d5a6CC9D4B2b7FAD",
+                "sigDeadline": "0x6553f100"
+            }
+        });
+        let json = serde_json::from_value::<crate::EIP712>(json).unwrap();
+
+        assert_eq!(
+            hash_structured_data(json).unwrap(),
+            hash_structured_data((&domain, &single)).unwrap()
+        );
+        // PermitHash.hash(PermitSingle) and hash(PermitBatch) under the mainnet
+        // domain, computed with a separate keccak and ABI encoding rather than this crate
+        assert_eq!(
+            format!("{:x}", hash_structured_data((&domain, &single)).unwrap()),
+            "ca312a271494e2db6bab4f6679f9d0368ae8bfd4ab3b5c8d651ef24602a23a34"
+        );
+        assert_eq!(
+            format!("{:x}", hash_structured_data((&domain, &batch)).unwrap()),
+            "abc3aacb395625af5f192b7ce2251ff4c5a2df1adda9c2c078bff21d273d2082"
+        );
+    }
+
+    #[test]
+    fn it_appends_witnesses() {
+        let permit = PermitWitness::new(transfer(), example_witness());
+
+        assert_eq!(
+            permit.witness_type_string().unwrap(),
+            "ExampleWitness witness)ExampleWitness(address user)TokenPermissions(address token,uint256 amount)"
+        );
+        let full_type = format!(
+            "PermitWitnessTransferFrom(TokenPermissions permitted,address spender,uint256 nonce,uint256 deadline,{}",
+            permit.witness_type_string().unwrap()
+        );
+        assert_eq!(
+            encode_type(
+                "PermitWitnessTransferFrom",
+                &permit.message_types().unwrap()
+            )
+            .unwrap(),
+            full_type
+        );
+
+        // Permit2's hashWithWitness
+        let domain = permit2_domain(1u64);
+        let transfer = transfer();
+        let struct_hash = keccak(
+            [
+                keccak(&full_type),
+                transfer.permitted.struct_hash(),
+                transfer.spender.encode_word(),
+                transfer.nonce.encode_word(),
+                transfer.deadline.encode_word(),
+                permit.witness.hash().unwrap(),
+            ]
+            .iter()
+            .flat_map(|word| word.0)
+            .collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            permit.digest(&domain).unwrap(),
+            crate::encode::hash_typed_message(&domain.separator(), &struct_hash)
+        );
+        // the same digest, computed with a separate keccak and ABI encoding
+        assert_eq!(
+            format!("{:x}", permit.digest(&domain).unwrap()),
+            "ab550ca9933128eb013053a4901fffc4a09d4d7c2793bdda4318ea0145a09120"
+        );
+    }
+
+    #[test]
+    fn it_appends_witnesses_to_batches() {
+        let transfer = transfer();
+        let batch = PermitBatchTransferFrom {
+            permitted: vec![transfer.permitted.clone(), transfer.permitted],
+            spender: spender(),
+            nonce: 1.into(),
+            deadline: 1_700_000_000.into(),
+        };
+        let permit = PermitWitness::new(batch, example_witness().with_field_name("order"));
+
+        assert_eq!(
+            permit.witness_type_string().unwrap(),
+            "ExampleWitness order)ExampleWitness(address user)TokenPermissions(address token,uint256 amount)"
+        );
+        // hashWithWitness for the batch, computed with a separate keccak and ABI encoding
+        assert_eq!(
+            format!("{:x}", permit.digest(&permit2_domain(1u64)).unwrap()),
+            "e7c01ec52142f6a03b1f76f63aa4874a46ac465a3af63376543ca09ca49770e2"
+        );
+    }
+
+    #[test]
+    fn it_rejects_conflicting_witness_types() {
+        let mut witness = example_witness();
+        witness.types.insert(
+            "TokenPermissions".into(),
+            vec![FieldType::new("token", "address")],
+        );
+        assert_eq!(
+            PermitWitness::new(transfer(), witness)
+                .message_types()
+                .unwrap_err()
+                .kind(),
+            ErrorKind::DuplicateType("TokenPermissions".into())
+        );
+
+        let permit = PermitWitness::new(transfer(), example_witness().with_field_name("nonce"));
+        assert!(permit.message_types().is_err());
+
+        let mut witness = example_witness();
+        witness.types.clear();
+        assert!(PermitWitness::new(transfer(), witness)
+            .digest(&permit2_domain(1u64))
+            .is_err());
+    }
+}
@@ -0,0 +1,444 @@
+//! Safe (formerly Gnosis Safe) transaction and message hashes, and the
+//! signature blob `execTransaction` expects
+use crate::eip712::{FieldType, MessageTypes};
+use crate::encode::hash_typed_message;
+use crate::error::{ErrorKind, Result};
+use crate::signing::{address, sign, Signature};
+use crate::typed::{Eip712Struct, Eip712Type, Uint8};
+use ethereum_types::{Address, H256, U256};
+use keccak_hash::keccak;
+use secp256k1::SecretKey;
+
+fn hash_words(words: &[H256]) -> H256 {
+    keccak(words.iter().flat_map(|word| word.0).collect::<Vec<_>>())
+}
+
+/// A Safe contract version, e.g. `1.3.0` or `1.4.1+L2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SafeVersion(pub u16, pub u16, pub u16);
+
+impl SafeVersion {
+    pub fn parse(version: &str) -> Result<Self> {
+        let invalid = || ErrorKind::UnsupportedVersion(version.to_owned());
+        let numbers = version
+            .trim_end_matches("+L2")
+            .split('.')
+            .map(|part| part.parse::<u16>().map_err(|_| invalid()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        match numbers[..] {
+            [major, minor, patch] => Ok(SafeVersion(major, minor, patch)),
+            _ => Err(invalid())?,
+        }
+    }
+
+    /// from 1.3.0 on the domain includes the chain id
+    pub fn has_chain_id(&self) -> bool {
+        *self >= SafeVersion(1, 3, 0)
+    }
+
+    /// before 1.0.0 `baseGas` was called `dataGas`
+    pub fn uses_base_gas(&self) -> bool {
+        *self >= SafeVersion(1, 0, 0)
+    }
+}
+
+/// The kind of call a Safe transaction makes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Call = 0,
+    DelegateCall = 1,
+}
+
+/// `SafeTx(address to,uint256 value,bytes data,uint8 operation,uint256 safeTxGas,uint256 baseGas,uint256 gasPrice,address gasToken,address refundReceiver,uint256 nonce)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafeTx {
+    pub to: Address,
+    pub value: U256,
+    pub data: Vec<u8>,
+    pub operation: Operation,
+    pub safe_tx_gas: U256,
+    /// `dataGas` before Safe 1.0.0
+    pub base_gas: U256,
+    pub gas_price: U256,
+    pub gas_token: Address,
+    pub refund_receiver: Address,
+    pub nonce: U256,
+}
+
+impl SafeTx {
+    /// a call without gas refunds, as most transactions are
+    pub fn call(to: Address, value: U256, data: Vec<u8>, nonce: U256) -> Self {
+        SafeTx {
+            to,
+            value,
+            data,
+            operation: Operation::Call,
+            safe_tx_gas: 0.into(),
+            base_gas: 0.into(),
+            gas_price: 0.into(),
+            gas_token: Address::zero(),
+            refund_receiver: Address::zero(),
+            nonce,
+        }
+    }
+}
+
+impl Eip712Struct for SafeTx {
+    fn type_name() -> String {
+        "SafeTx".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("to", "address"),
+            FieldType::new("value", "uint256"),
+            FieldType::new("data", "bytes"),
+            FieldType::new("operation", "uint8"),
+            FieldType::new("safeTxGas", "uint256"),
+            FieldType::new("baseGas", "uint256"),
+            FieldType::new("gasPrice", "uint256"),
+            FieldType::new("gasToken", "address"),
+            FieldType::new("refundReceiver", "address"),
+            FieldType::new("nonce", "uint256"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.to.encode_word(),
+            self.value.encode_word(),
+            self.data.encode_word(),
+            Uint8(self.operation as u8).encode_word(),
+            self.safe_tx_gas.encode_word(),
+            self.base_gas.encode_word(),
+            self.gas_price.encode_word(),
+            self.gas_token.encode_word(),
+            self.refund_receiver.encode_word(),
+            self.nonce.encode_word(),
+        ]
+    }
+}
+
+/// `SafeMessage(bytes message)`, an off-chain message checked through EIP-1271
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafeMessage {
+    pub message: Vec<u8>,
+}
+
+impl SafeMessage {
+    /// the message `isValidSignature(bytes32 hash, bytes signature)` checks,
+    /// i.e. `abi.encode(hash)`
+    pub fn from_hash(hash: H256) -> Self {
+        SafeMessage {
+            message: hash.0.to_vec(),
+        }
+    }
+}
+
+impl Eip712Struct for SafeMessage {
+    fn type_name() -> String {
+        "SafeMessage".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![FieldType::new("message", "bytes")]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![self.message.encode_word()]
+    }
+}
+
+/// A deployed Safe, whose version determines the domain and `SafeTx` layout
+#[derive(Debug, Clone, PartialEq)]
+pub struct Safe {
+    pub address: Address,
+    pub chain_id: U256,
+    pub version: SafeVersion,
+}
+
+impl Safe {
+    pub fn new<C: Into<U256>>(address: Address, chain_id: C, version: &str) -> Result<Self> {
+        Ok(Safe {
+            address,
+            chain_id: chain_id.into(),
+            version: SafeVersion::parse(version)?,
+        })
+    }
+
+    /// `EIP712Domain(uint256 chainId,address verifyingContract)` from 1.3.0 on,
+    /// `EIP712Domain(address verifyingContract)` before
+    pub fn domain_separator(&self) -> H256 {
+        if self.version.has_chain_id() {
+            hash_words(&[
+                keccak("EIP712Domain(uint256 chainId,address verifyingContract)"),
+                self.chain_id.encode_word(),
+                self.address.encode_word(),
+            ])
+        } else {
+            hash_words(&[
+                keccak("EIP712Domain(address verifyingContract)"),
+                self.address.encode_word(),
+            ])
+        }
+    }
+
+    /// the `safeTxHash` owners sign, as returned by `getTransactionHash`
+    pub fn transaction_hash(&self, transaction: &SafeTx) -> H256 {
+        let type_hash = if self.version.uses_base_gas() {
+            SafeTx::type_hash()
+        } else {
+            keccak(SafeTx::encode_type().replace("baseGas", "dataGas"))
+        };
+        let mut words = vec![type_hash];
+        words.extend(transaction.encode_data());
+        hash_typed_message(&self.domain_separator(), &hash_words(&words))
+    }
+
+    /// the hash owners sign for an EIP-1271 message, as returned by `getMessageHash`
+    pub fn message_hash(&self, message: &SafeMessage) -> H256 {
+        hash_typed_message(&self.domain_separator(), &message.struct_hash())
+    }
+}
+
+/// One owner's confirmation of a Safe hash
+#[derive(Debug, Clone, PartialEq)]
+pub enum SafeSignature {
+    /// an ECDSA signature of the hash
+    Eoa(Address, Signature),
+    /// an ECDSA signature of the hash with the `eth_sign` prefix
+    EthSign(Address, Signature),
+    /// an owner that called `approveHash`, or that submits the transaction itself
+    ApprovedHash(Address),
+    /// an EIP-1271 contract owner and the signature data it validates
+    Contract(Address, Vec<u8>),
+}
+
+impl SafeSignature {
+    /// signs `hash` with an owner key
+    pub fn sign(hash: &H256, secret_key: &SecretKey) -> Self {
+        SafeSignature::Eoa(address(secret_key), sign(hash, secret_key))
+    }
+
+    pub fn owner(&self) -> Address {
+        match self {
+            SafeSignature::Eoa(owner, _)
+            | SafeSignature::EthSign(owner, _)
+            | SafeSignature::ApprovedHash(owner)
+            | SafeSignature::Contract(owner, _) => *owner,
+        }
+    }
+}
+
+/// Concatenates signatures sorted by owner, as `checkSignatures` requires. Every
+/// signature takes 65 bytes, contract signature data is appended after all of
+/// them and referenced by offset.
+pub fn encode_signatures(signatures: &[SafeSignature]) -> Result<Vec<u8>> {
+    let mut sorted = signatures.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|signature| signature.owner());
+    if let Some(pair) = sorted
+        .windows(2)
+        .find(|pair| pair[0].owner() == pair[1].owner())
+    {
+        Err(ErrorKind::InvalidSignature(format!(
+            "owner 0x{:x} signed twice",
+            pair[0].owner()
+        )))?;
+    }
+
+    let mut encoded = Vec::with_capacity(65 * sorted.len());
+    let mut dynamic = vec![];
+    for signature in sorted {
+        let (r, s, v) = match signature {
+            SafeSignature::Eoa(_, signature) => (signature.r, signature.s, signature.v),
+            SafeSignature::EthSign(_, signature) => (signature.r, signature.s, signature.v + 4),
+            SafeSignature::ApprovedHash(owner) => (owner.encode_word(), H256::zero(), 1),
+            SafeSignature::Contract(owner, data) => {
+                let offset = U256::from(65 * signatures.len() + dynamic.len());
+                dynamic.extend_from_slice(&U256::from(data.len()).encode_word().0);
+                dynamic.extend_from_slice(data);
+                (owner.encode_word(), offset.encode_word(), 0)
+            }
+        };
+        encoded.extend_from_slice(&r.0);
+        encoded.extend_from_slice(&s.0);
+        encoded.push(v);
+    }
+    encoded.extend(dynamic);
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::recover;
+    use rustc_hex::FromHex;
+
+    fn safe(version: &str) -> Safe {
+        Safe::new(Address::from(&[0x5a; 20][..]), 1u64, version).unwrap()
+    }
+
+    fn transaction() -> SafeTx {
+        SafeTx::call(Address::from(&[0x22; 20][..]), 1.into(), vec![], 7.into())
+    }
+
+    #[test]
+    fn it_uses_the_safe_type_hashes() {
+        // the constants from Safe.sol and CompatibilityFallbackHandler.sol
+        assert_eq!(
+            format!("{:x}", SafeTx::type_hash()),
+            "bb8310d486368db6bd6f849402fdd73ad53d316b5a4b2644ad6efe0f941286d8"
+        );
+        assert_eq!(
+            format!("{:x}", SafeMessage::type_hash()),
+            "60b3cbf8b4a223d68d641b3b6ddf9a298e7f33710cf3d3a9d1146b5a6150fbca"
+        );
+        assert_eq!(
+            format!(
+                "{:x}",
+                keccak("EIP712Domain(uint256 chainId,address verifyingContract)")
+            ),
+            "47e79534a245952e8b16893a336b85a3d9ea9fa8c573f3d803afb92a79469218"
+        );
+        assert_eq!(
+            format!("{:x}", keccak("EIP712Domain(address verifyingContract)")),
+            "035aff83d86937d35b32e04f0ddc6ff469290eef2f1b692d8a815c89404d4749"
+        );
+    }
+
+    #[test]
+    fn it_hashes_per_version() {
+        let transaction = transaction();
+        let modern = safe("1.3.0+L2").transaction_hash(&transaction);
+        let without_chain_id = safe("1.2.0").transaction_hash(&transaction);
+        let with_data_gas = safe("0.1.0").transaction_hash(&transaction);
+
+        assert_eq!(modern, safe("1.4.1").transaction_hash(&transaction));
+        assert_ne!(modern, without_chain_id);
+        assert_ne!(without_chain_id, with_data_gas);
+        assert_eq!(
+            safe("1.2.0").domain_separator(),
+            safe("1.1.1").domain_separator()
+        );
+        assert_ne!(
+            Safe::new(Address::from(&[0x5a; 20][..]), 5u64, "1.2.0")
+                .unwrap()
+                .domain_separator(),
+            Safe::new(Address::from(&[0x5a; 20][..]), 5u64, "1.3.0")
+                .unwrap()
+                .domain_separator()
+        );
+        assert_eq!(
+            with_data_gas,
+            hash_typed_message(
+                &safe("0.1.0").domain_separator(),
+                &hash_words(
+                    &[
+                        vec![keccak(
+                            "SafeTx(address to,uint256 value,bytes data,uint8 operation,uint256 safeTxGas,uint256 dataGas,uint256 gasPrice,address gasToken,address refundReceiver,uint256 nonce)"
+                        )],
+                        transaction.encode_data(),
+                    ]
+                    .concat()
+                )
+            )
+        );
+        assert_eq!(
+            SafeVersion::parse("1.3").unwrap_err().kind(),
+            ErrorKind::UnsupportedVersion("1.3".into())
+        );
+    }
+
+    #[test]
+    fn it_matches_hand_encoded_safe_tx_hashes() {
+        // transfer(0x2222…, 1 USDC) on the USDC contract, nonce 7
+        let data = "a9059cbb\
+                    0000000000000000000000002222222222222222222222222222222222222222\
+                    00000000000000000000000000000000000000000000000000000000000f4240"
+            .from_hex()
+            .unwrap();
+        let transaction = SafeTx::call(
+            "A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48".parse().unwrap(),
+            0.into(),
+            data,
+            7.into(),
+        );
+        let sepolia = Safe::new(Address::from(&[0x5a; 20][..]), 11_155_111u64, "1.3.0").unwrap();
+
+        // computed with a separate keccak and ABI encoding that follows
+        // getTransactionHash, rather than this crate
+        assert_eq!(
+            format!("{:x}", sepolia.transaction_hash(&transaction)),
+            "77a9633623d6a9926b0dd514b39a85921c95b893ee92ae0f025bfaa4ab19fa6a"
+        );
+        assert_eq!(
+            format!("{:x}", safe("1.1.1").transaction_hash(&transaction)),
+            "75d375d4d68991d708343b06476256dfe5a9d7081b0d485c9cdfbbd114b80629"
+        );
+    }
+
+    #[test]
+    fn it_hashes_safe_messages() {
+        let safe = safe("1.3.0");
+        let hash = keccak("hello");
+
+        assert_eq!(
+            safe.message_hash(&SafeMessage::from_hash(hash)),
+            hash_typed_message(
+                &safe.domain_separator(),
+                &hash_words(&[SafeMessage::type_hash(), keccak(hash)])
+            )
+        );
+    }
+
+    #[test]
+    fn it_encodes_sorted_signatures() {
+        let safe = safe("1.3.0");
+        let hash = safe.transaction_hash(&transaction());
+        let keys = [0x11u8, 0x22]
+            .iter()
+            .map(|byte| SecretKey::from_slice(&[*byte; 32]).unwrap())
+            .collect::<Vec<_>>();
+        let contract = Address::from(&[0x01; 20][..]);
+        let approver = Address::from(&[0xff; 20][..]);
+        let signatures = vec![
+            SafeSignature::sign(&hash, &keys[0]),
+            SafeSignature::ApprovedHash(approver),
+            SafeSignature::Contract(contract, vec![0xc0, 0xde]),
+            SafeSignature::sign(&hash, &keys[1]),
+        ];
+        let mut owners = signatures.iter().map(|s| s.owner()).collect::<Vec<_>>();
+        owners.sort();
+
+        let encoded = encode_signatures(&signatures).unwrap();
+        assert_eq!(encoded.len(), 4 * 65 + 32 + 2);
+        for (index, owner) in owners.iter().enumerate() {
+            let part = &encoded[65 * index..65 * (index + 1)];
+            match part[64] {
+                0 => {
+                    assert_eq!(*owner, contract);
+                    assert_eq!(&part[12..32], &contract.0[..]);
+                    assert_eq!(part[32..64], U256::from(4 * 65).encode_word().0);
+                }
+                1 => {
+                    assert_eq!(*owner, approver);
+                    assert_eq!(&part[12..32], &approver.0[..]);
+                }
+                _ => assert_eq!(
+                    recover(&hash, &Signature::from_bytes(part).unwrap()).unwrap(),
+                    *owner
+                ),
+            }
+        }
+        assert_eq!(encoded[4 * 65..4 * 65 + 32], U256::from(2).encode_word().0);
+        assert_eq!(encoded[4 * 65 + 32..], [0xc0, 0xde]);
+
+        let eth_sign = SafeSignature::EthSign(approver, sign(&hash, &keys[0]));
+        assert!(encode_signatures(std::slice::from_ref(&eth_sign)).unwrap()[64] > 30);
+        assert!(encode_signatures(&[eth_sign, SafeSignature::ApprovedHash(approver)]).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! JSON Schema generation
+use crate::eip712::MessageTypes;
+use crate::encode::sorted_dependencies;
+use crate::error::Result;
+use crate::parser::{Parser, Type};
+use serde_json::{json, Map, Value};
+
+const HEX: &str = "[0-9a-fA-F]";
+
+/// Generates a JSON Schema (draft 2020-12) for messages of `primary_type`,
+/// accepting the same JSON the encoder does: integers are 0x-prefixed hex
+/// strings in two's complement that fit into the declared width, addresses
+/// have 40 hex digits and `bytesN` values at most N bytes. Every struct field is
+/// required, unknown properties are ignored just like the encoder ignores them.
+pub fn generate_json_schema(message_types: &MessageTypes, primary_type: &str) -> Result<Value> {
+    let parser = Parser::new();
+    let mut defs = Map::new();
+
+    for dep in sorted_dependencies(primary_type, message_types)? {
+        let mut properties = Map::new();
+        for field in &message_types[dep] {
+            let type_ = parser.parse_type(&field.type_)?;
+            properties.insert(field.name.clone(), type_schema(&type_));
+        }
+        let required = message_types[dep]
+            .iter()
+            .map(|field| field.name.clone())
+            .collect::<Vec<_>>();
+        defs.insert(
+            dep.to_owned(),
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            }),
+        );
+    }
+
+    Ok(json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": primary_type,
+        "$ref": format!("#/$defs/{}", primary_type),
+        "$defs": defs,
+    }))
+}
+
+fn type_schema(type_: &Type) -> Value {
+    match type_ {
+        Type::Address => json!({
+            "type": "string",
+            "pattern": format!("^0x{}{{40}}$", HEX),
+        }),
+        Type::Uint(bits) => json!({
+            "type": "string",
+            "pattern": uint_pattern(*bits),
+            "maxLength": 66,
+        }),
+        Type::Int(bits) => json!({
+            "type": "string",
+            "pattern": int_pattern(*bits),
+            "maxLength": 66,
+        }),
+        Type::String => json!({ "type": "string" }),
+        Type::Bool => json!({ "type": "boolean" }),
+        Type::Bytes => json!({
+            "type": "string",
+            "pattern": format!("^0x({}{{2}})*$", HEX),
+        }),
+        Type::Byte(len) => json!({
+            "type": "string",
+            "pattern": format!("^0x({}{{2}}){{0,{}}}$", HEX, len),
+        }),
+        Type::Custom(name) => json!({ "$ref": format!("#/$defs/{}", name) }),
+        Type::Array { inner, length } => {
+            let mut schema = json!({
+                "type": "array",
+                "items": type_schema(inner),
+            });
+            if let Some(length) = length {
+                schema["minItems"] = json!(length);
+                schema["maxItems"] = json!(length);
+            }
+            schema
+        }
+    }
+}
+
+/// at most `bits / 4` significant hex digits
+fn uint_pattern(bits: u16) -> String {
+    format!("^0x0*{}{{0,{}}}$", HEX, bits / 4)
+}
+
+/// non-negative values have a clear sign bit within `bits`, negative ones are
+/// 64 digits of two's complement where everything above the sign bit is set
+fn int_pattern(bits: u16) -> String {
+    let digits = bits / 4;
+    format!(
+        "^0x(0*[0-7]?{hex}{{0,{rest}}}|[fF]{{{ones}}}[89a-fA-F]{hex}{{{rest}}})$",
+        hex = HEX,
+        rest = digits - 1,
+        ones = 64 - digits,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eip712::FieldType;
+    use crate::encode::hash_struct;
+    use regex::Regex;
+    use std::collections::HashMap;
+
+    fn types(type_: &str) -> MessageTypes {
+        let mut types = HashMap::new();
+        types.insert(
+            "Test".to_string(),
+            vec![FieldType {
+                name: "value".into(),
+                type_: type_.into(),
+            }],
+        );
+        types
+    }
+
+    /// whether the schema's pattern and length limit and the encoder agree on `values`
+    fn assert_agrees<S: AsRef<str>>(type_: &str, values: &[S]) {
+        let parser = Parser::new();
+        let types = types(type_);
+        let schema = generate_json_schema(&types, "Test").unwrap();
+        let field = &schema["$defs"]["Test"]["properties"]["value"];
+        let pattern = Regex::new(field["pattern"].as_str().unwrap()).unwrap();
+        let max_length = field["maxLength"].as_u64().unwrap_or(u64::MAX) as usize;
+
+        for value in values.iter().map(AsRef::as_ref) {
+            let valid = pattern.is_match(value) && value.len() <= max_length;
+            let encoded = hash_struct(&parser, "Test", &types, &json!({ "value": value }));
+            assert_eq!(valid, encoded.is_ok(), "{} {}: {:?}", type_, value, encoded);
+        }
+    }
+
+    #[test]
+    fn it_generates_nested_definitions() {
+        let mut types = HashMap::new();
+        types.insert(
+            "Person".to_string(),
+            vec![FieldType {
+                name: "wallets".into(),
+                type_: "address[2]".into(),
+            }],
+        );
+        types.insert(
+            "Mail".to_string(),
+            vec![
+                FieldType {
+                    name: "to".into(),
+                    type_: "Person[]".into(),
+                },
+                FieldType {
+                    name: "urgent".into(),
+                    type_: "bool".into(),
+                },
+            ],
+        );
+
+        assert_eq!(
+            generate_json_schema(&types, "Mail").unwrap(),
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "title": "Mail",
+                "$ref": "#/$defs/Mail",
+                "$defs": {
+                    "Mail": {
+                        "type": "object",
+                        "properties": {
+                            "to": { "type": "array", "items": { "$ref": "#/$defs/Person" } },
+                            "urgent": { "type": "boolean" },
+                        },
+                        "required": ["to", "urgent"],
+                    },
+                    "Person": {
+                        "type": "object",
+                        "properties": {
+                            "wallets": {
+                                "type": "array",
+                                "items": { "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$" },
+                                "minItems": 2,
+                                "maxItems": 2,
+                            },
+                        },
+                        "required": ["wallets"],
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn it_matches_the_encoder_for_integers() {
+        let mut values = vec!["0x".to_owned(), "7f".to_owned(), "0x00000080".to_owned()];
+        for byte in 0..=255u8 {
+            values.push(format!("0x{:x}", byte));
+            values.push(format!("0x{:02X}", byte));
+            values.push(format!("0x{}{:02x}", "f".repeat(62), byte));
+            values.push(format!("0x{:02x}{}", byte, "0".repeat(62)));
+        }
+        values.push(format!("0x{}", "0".repeat(65)));
+        values.push(format!("0x{}1", "0".repeat(64)));
+
+        for type_ in &["uint8", "uint16", "uint256", "int8", "int16", "int256"] {
+            assert_agrees(type_, &values);
+        }
+    }
+
+    #[test]
+    fn it_matches_the_encoder_for_hex_strings() {
+        let values = [
+            "0x",
+            "0x1",
+            "0x12",
+            "0x123456",
+            "0xzz",
+            "12",
+            "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826",
+            "00CD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826",
+            "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD82",
+        ];
+
+        for type_ in &["address", "bytes", "bytes1", "bytes3"] {
+            assert_agrees(type_, &values);
+        }
+    }
+}
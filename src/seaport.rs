@@ -0,0 +1,603 @@
+//! Seaport orders, signed one by one or as a bulk order over a tree of orders
+use crate::eip712::{hex, EIP712Domain, FieldType, MessageTypes};
+use crate::encode::hash_typed_message;
+use crate::error::{ErrorKind, Result};
+use crate::nft_helpers::{generate_typed_data, MessageSchema};
+use crate::signing::sign;
+use crate::typed::{Eip712Struct, Eip712Type, Uint8};
+use ethereum_types::{Address, H256, U256};
+use keccak_hash::keccak;
+use secp256k1::SecretKey;
+use serde_json::{json, Value};
+
+/// the address Seaport 1.6 is deployed at on every chain
+pub const SEAPORT_1_6_ADDRESS: &str = "0000000000000068F116a894984e2DB1123eB395";
+
+/// the deepest bulk order tree Seaport accepts, i.e. at most 2^24 orders
+pub const MAX_BULK_ORDER_HEIGHT: usize = 24;
+
+/// the domain of Seaport 1.6 on `chain_id`
+pub fn seaport_domain<C: Into<U256>>(chain_id: C) -> EIP712Domain {
+    EIP712Domain::from_parts(
+        "Seaport",
+        "1.6",
+        chain_id,
+        SEAPORT_1_6_ADDRESS
+            .parse()
+            .expect("the address is valid hex; qed"),
+    )
+}
+
+/// the `OrderComponents` schema and its item types
+pub fn order_schema() -> MessageSchema {
+    MessageSchema {
+        primary_type: OrderComponents::type_name(),
+        types: OrderComponents::message_types(),
+    }
+}
+
+/// What kind of asset an item transfers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemType {
+    Native = 0,
+    Erc20 = 1,
+    Erc721 = 2,
+    Erc1155 = 3,
+    Erc721WithCriteria = 4,
+    Erc1155WithCriteria = 5,
+}
+
+/// Who may fulfill an order and whether it may be filled partially
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderType {
+    FullOpen = 0,
+    PartialOpen = 1,
+    FullRestricted = 2,
+    PartialRestricted = 3,
+    Contract = 4,
+}
+
+/// `OfferItem(uint8 itemType,address token,uint256 identifierOrCriteria,uint256 startAmount,uint256 endAmount)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfferItem {
+    pub item_type: ItemType,
+    pub token: Address,
+    pub identifier_or_criteria: U256,
+    pub start_amount: U256,
+    pub end_amount: U256,
+}
+
+impl OfferItem {
+    /// a single ERC-721 token
+    pub fn erc721(token: Address, token_id: U256) -> Self {
+        OfferItem {
+            item_type: ItemType::Erc721,
+            token,
+            identifier_or_criteria: token_id,
+            start_amount: 1.into(),
+            end_amount: 1.into(),
+        }
+    }
+
+    /// a fixed amount of an ERC-1155 token
+    pub fn erc1155(token: Address, token_id: U256, amount: U256) -> Self {
+        OfferItem {
+            item_type: ItemType::Erc1155,
+            token,
+            identifier_or_criteria: token_id,
+            start_amount: amount,
+            end_amount: amount,
+        }
+    }
+
+    /// a fixed amount of an ERC-20 token
+    pub fn erc20(token: Address, amount: U256) -> Self {
+        OfferItem {
+            item_type: ItemType::Erc20,
+            token,
+            identifier_or_criteria: 0.into(),
+            start_amount: amount,
+            end_amount: amount,
+        }
+    }
+
+    /// the item received by `recipient`
+    pub fn to(self, recipient: Address) -> ConsiderationItem {
+        ConsiderationItem {
+            item_type: self.item_type,
+            token: self.token,
+            identifier_or_criteria: self.identifier_or_criteria,
+            start_amount: self.start_amount,
+            end_amount: self.end_amount,
+            recipient,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "itemType": hex(self.item_type as u8),
+            "token": hex(self.token),
+            "identifierOrCriteria": hex(self.identifier_or_criteria),
+            "startAmount": hex(self.start_amount),
+            "endAmount": hex(self.end_amount),
+        })
+    }
+}
+
+impl Eip712Struct for OfferItem {
+    fn type_name() -> String {
+        "OfferItem".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("itemType", "uint8"),
+            FieldType::new("token", "address"),
+            FieldType::new("identifierOrCriteria", "uint256"),
+            FieldType::new("startAmount", "uint256"),
+            FieldType::new("endAmount", "uint256"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            Uint8(self.item_type as u8).encode_word(),
+            self.token.encode_word(),
+            self.identifier_or_criteria.encode_word(),
+            self.start_amount.encode_word(),
+            self.end_amount.encode_word(),
+        ]
+    }
+}
+
+/// `ConsiderationItem(uint8 itemType,address token,uint256 identifierOrCriteria,uint256 startAmount,uint256 endAmount,address recipient)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsiderationItem {
+    pub item_type: ItemType,
+    pub token: Address,
+    pub identifier_or_criteria: U256,
+    pub start_amount: U256,
+    pub end_amount: U256,
+    pub recipient: Address,
+}
+
+impl ConsiderationItem {
+    /// a fixed amount of the chain's native currency
+    pub fn native(amount: U256, recipient: Address) -> Self {
+        ConsiderationItem {
+            item_type: ItemType::Native,
+            token: Address::zero(),
+            identifier_or_criteria: 0.into(),
+            start_amount: amount,
+            end_amount: amount,
+            recipient,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "itemType": hex(self.item_type as u8),
+            "token": hex(self.token),
+            "identifierOrCriteria": hex(self.identifier_or_criteria),
+            "startAmount": hex(self.start_amount),
+            "endAmount": hex(self.end_amount),
+            "recipient": hex(self.recipient),
+        })
+    }
+}
+
+impl Eip712Struct for ConsiderationItem {
+    fn type_name() -> String {
+        "ConsiderationItem".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("itemType", "uint8"),
+            FieldType::new("token", "address"),
+            FieldType::new("identifierOrCriteria", "uint256"),
+            FieldType::new("startAmount", "uint256"),
+            FieldType::new("endAmount", "uint256"),
+            FieldType::new("recipient", "address"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            Uint8(self.item_type as u8).encode_word(),
+            self.token.encode_word(),
+            self.identifier_or_criteria.encode_word(),
+            self.start_amount.encode_word(),
+            self.end_amount.encode_word(),
+            self.recipient.encode_word(),
+        ]
+    }
+}
+
+/// `OrderComponents(address offerer,address zone,OfferItem[] offer,ConsiderationItem[] consideration,uint8 orderType,uint256 startTime,uint256 endTime,bytes32 zoneHash,uint256 salt,bytes32 conduitKey,uint256 counter)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderComponents {
+    pub offerer: Address,
+    pub zone: Address,
+    pub offer: Vec<OfferItem>,
+    pub consideration: Vec<ConsiderationItem>,
+    pub order_type: OrderType,
+    pub start_time: U256,
+    pub end_time: U256,
+    pub zone_hash: H256,
+    pub salt: U256,
+    pub conduit_key: H256,
+    /// the offerer's current counter on Seaport
+    pub counter: U256,
+}
+
+impl OrderComponents {
+    /// an open order without a zone or conduit, valid from `start_time` until
+    /// `end_time` with a random salt
+    pub fn new(
+        offerer: Address,
+        offer: Vec<OfferItem>,
+        consideration: Vec<ConsiderationItem>,
+        start_time: u64,
+        end_time: u64,
+        counter: U256,
+    ) -> Self {
+        OrderComponents {
+            offerer,
+            zone: Address::zero(),
+            offer,
+            consideration,
+            order_type: OrderType::FullOpen,
+            start_time: start_time.into(),
+            end_time: end_time.into(),
+            zone_hash: H256::zero(),
+            salt: U256::from(&rand::random::<[u8; 32]>()[..]),
+            conduit_key: H256::zero(),
+            counter,
+        }
+    }
+
+    /// the order with every field zeroed, used to pad bulk order trees
+    fn empty() -> Self {
+        OrderComponents {
+            offerer: Address::zero(),
+            zone: Address::zero(),
+            offer: vec![],
+            consideration: vec![],
+            order_type: OrderType::FullOpen,
+            start_time: 0.into(),
+            end_time: 0.into(),
+            zone_hash: H256::zero(),
+            salt: 0.into(),
+            conduit_key: H256::zero(),
+            counter: 0.into(),
+        }
+    }
+
+    /// the order as JSON, e.g. for `generate_typed_data` with `order_schema()`
+    pub fn to_json(&self) -> Value {
+        json!({
+            "offerer": hex(self.offerer),
+            "zone": hex(self.zone),
+            "offer": self.offer.iter().map(OfferItem::to_json).collect::<Vec<_>>(),
+            "consideration": self
+                .consideration
+                .iter()
+                .map(ConsiderationItem::to_json)
+                .collect::<Vec<_>>(),
+            "orderType": hex(self.order_type as u8),
+            "startTime": hex(self.start_time),
+            "endTime": hex(self.end_time),
+            "zoneHash": hex(self.zone_hash),
+            "salt": hex(self.salt),
+            "conduitKey": hex(self.conduit_key),
+            "counter": hex(self.counter),
+        })
+    }
+}
+
+impl Eip712Struct for OrderComponents {
+    fn type_name() -> String {
+        "OrderComponents".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("offerer", "address"),
+            FieldType::new("zone", "address"),
+            FieldType::new("offer", &Vec::<OfferItem>::field_type()),
+            FieldType::new("consideration", &Vec::<ConsiderationItem>::field_type()),
+            FieldType::new("orderType", "uint8"),
+            FieldType::new("startTime", "uint256"),
+            FieldType::new("endTime", "uint256"),
+            FieldType::new("zoneHash", "bytes32"),
+            FieldType::new("salt", "uint256"),
+            FieldType::new("conduitKey", "bytes32"),
+            FieldType::new("counter", "uint256"),
+        ]
+    }
+
+    fn add_dependencies(types: &mut MessageTypes) {
+        Vec::<OfferItem>::add_field_types(types);
+        Vec::<ConsiderationItem>::add_field_types(types);
+    }
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.offerer.encode_word(),
+            self.zone.encode_word(),
+            self.offer.encode_word(),
+            self.consideration.encode_word(),
+            Uint8(self.order_type as u8).encode_word(),
+            self.start_time.encode_word(),
+            self.end_time.encode_word(),
+            self.zone_hash.encode_word(),
+            self.salt.encode_word(),
+            self.conduit_key.encode_word(),
+            self.counter.encode_word(),
+        ]
+    }
+}
+
+/// Several orders signed at once as `BulkOrder(OrderComponents[2]...[2] tree)`.
+/// The orders are the leaves of a complete binary tree of `height` levels,
+/// padded with empty orders; each order is fulfilled with its own signature
+/// that carries the proof of its leaf.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BulkOrder {
+    orders: Vec<OrderComponents>,
+    /// the struct hashes of every level, leaves first
+    levels: Vec<Vec<H256>>,
+}
+
+impl BulkOrder {
+    /// fails unless there are between 1 and 2^24 orders
+    pub fn new(orders: Vec<OrderComponents>) -> Result<Self> {
+        if orders.is_empty() || orders.len() > 1 << MAX_BULK_ORDER_HEIGHT {
+            Err(ErrorKind::InvalidMerkleTree(format!(
+                "a bulk order holds 1 to 2^{} orders, got {}",
+                MAX_BULK_ORDER_HEIGHT,
+                orders.len()
+            )))?;
+        }
+        let height = (usize::BITS - (orders.len() - 1).leading_zeros()).max(1) as usize;
+        let mut leaves = orders
+            .iter()
+            .map(Eip712Struct::struct_hash)
+            .collect::<Vec<_>>();
+        leaves.resize(1 << height, OrderComponents::empty().struct_hash());
+
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let level = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| keccak([pair[0].0, pair[1].0].concat()))
+                .collect();
+            levels.push(level);
+        }
+        Ok(BulkOrder { orders, levels })
+    }
+
+    pub fn orders(&self) -> &[OrderComponents] {
+        &self.orders
+    }
+
+    /// the number of `[2]` dimensions of the tree
+    pub fn height(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    /// the `BulkOrder` type and its dependencies
+    pub fn message_types(&self) -> MessageTypes {
+        let mut types = OrderComponents::message_types();
+        types.insert(
+            "BulkOrder".into(),
+            vec![FieldType::new(
+                "tree",
+                &format!("OrderComponents{}", "[2]".repeat(self.height())),
+            )],
+        );
+        types
+    }
+
+    /// the `hashStruct` of the bulk order
+    pub fn struct_hash(&self) -> H256 {
+        let type_hash = keccak(
+            crate::encode::encode_type("BulkOrder", &self.message_types())
+                .expect("the type was just inserted; qed"),
+        );
+        keccak([type_hash.0, self.levels[self.height()][0].0].concat())
+    }
+
+    /// the EIP-712 digest the offerer signs
+    pub fn digest(&self, domain: &EIP712Domain) -> H256 {
+        hash_typed_message(&domain.separator(), &self.struct_hash())
+    }
+
+    /// the typed data JSON to pass to `eth_signTypedData_v4`
+    pub fn typed_data(&self, domain: &EIP712Domain) -> Result<Value> {
+        let empty = OrderComponents::empty().to_json();
+        let mut nodes = (0..1 << self.height())
+            .map(|index| {
+                self.orders
+                    .get(index)
+                    .map(OrderComponents::to_json)
+                    .unwrap_or_else(|| empty.clone())
+            })
+            .collect::<Vec<_>>();
+        while nodes.len() > 1 {
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| Value::Array(pair.to_vec()))
+                .collect();
+        }
+        let schema = MessageSchema {
+            primary_type: "BulkOrder".into(),
+            types: self.message_types(),
+        };
+        generate_typed_data(domain, &schema, json!({ "tree": nodes[0] }))
+    }
+
+    /// the siblings of the `index`th order, from the leaves up
+    pub fn proof(&self, index: usize) -> Vec<H256> {
+        self.levels[..self.height()]
+            .iter()
+            .enumerate()
+            .map(|(level, hashes)| hashes[(index >> level) ^ 1])
+            .collect()
+    }
+
+    /// Signs the tree and returns the signature to fulfill every order with:
+    /// the 65-byte signature, the order's index as a `uint24` and its proof
+    pub fn sign(&self, domain: &EIP712Domain, secret_key: &SecretKey) -> Vec<Vec<u8>> {
+        let signature = sign(&self.digest(domain), secret_key).to_bytes();
+        (0..self.orders.len())
+            .map(|index| {
+                let mut encoded = signature.to_vec();
+                encoded.extend_from_slice(&(index as u32).to_be_bytes()[1..]);
+                for sibling in self.proof(index) {
+                    encoded.extend_from_slice(&sibling.0);
+                }
+                encoded
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eip712::EIP712;
+    use crate::encode::hash_structured_data;
+    use crate::signing::{address, recover, test_key, Signature};
+
+    fn order(token_id: u64) -> OrderComponents {
+        order_from(address(&test_key()), token_id)
+    }
+
+    fn order_from(offerer: Address, token_id: u64) -> OrderComponents {
+        let mut order = OrderComponents::new(
+            offerer,
+            vec![OfferItem::erc721(
+                Address::from(&[0xcc; 20][..]),
+                token_id.into(),
+            )],
+            vec![
+                ConsiderationItem::native(975_000.into(), offerer),
+                ConsiderationItem::native(25_000.into(), Address::from(&[0xfe; 20][..])),
+            ],
+            1_700_000_000,
+            1_800_000_000,
+            0.into(),
+        );
+        order.salt = token_id.into();
+        order
+    }
+
+    #[test]
+    fn it_uses_the_seaport_type_hashes() {
+        assert_eq!(
+            OrderComponents::encode_type(),
+            "OrderComponents(address offerer,address zone,OfferItem[] offer,ConsiderationItem[] consideration,uint8 orderType,uint256 startTime,uint256 endTime,bytes32 zoneHash,uint256 salt,bytes32 conduitKey,uint256 counter)\
+             ConsiderationItem(uint8 itemType,address token,uint256 identifierOrCriteria,uint256 startAmount,uint256 endAmount,address recipient)\
+             OfferItem(uint8 itemType,address token,uint256 identifierOrCriteria,uint256 startAmount,uint256 endAmount)"
+        );
+        // Seaport's _ORDER_TYPEHASH
+        assert_eq!(
+            format!("{:x}", OrderComponents::type_hash()),
+            "fa445660b7e21515a59617fcd68910b487aa5808b8abda3d78bc85df364b2c2f"
+        );
+    }
+
+    #[test]
+    fn it_hashes_orders_like_the_json_encoder() {
+        let order = order(1);
+        let typed_data =
+            generate_typed_data(&seaport_domain(1u64), &order_schema(), order.to_json()).unwrap();
+        let typed_data = serde_json::from_value::<EIP712>(typed_data).unwrap();
+
+        assert_eq!(
+            hash_structured_data(typed_data).unwrap(),
+            hash_structured_data((&seaport_domain(1u64), &order)).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_hashes_bulk_orders_like_the_json_encoder() {
+        for (count, height) in [(1, 1), (2, 1), (3, 2), (5, 3)] {
+            let bulk = BulkOrder::new((0..count).map(order).collect()).unwrap();
+            let typed_data = bulk.typed_data(&seaport_domain(1u64)).unwrap();
+            let typed_data = serde_json::from_value::<EIP712>(typed_data).unwrap();
+
+            assert_eq!(bulk.height(), height);
+            assert_eq!(
+                hash_structured_data(typed_data).unwrap(),
+                bulk.digest(&seaport_domain(1u64))
+            );
+        }
+        assert!(BulkOrder::new(vec![]).is_err());
+    }
+
+    #[test]
+    fn it_matches_hand_encoded_order_hashes() {
+        let offerer = Address::from(&[0xab; 20][..]);
+        let domain = seaport_domain(1u64);
+        let bulk = BulkOrder::new((0..3).map(|id| order_from(offerer, id)).collect()).unwrap();
+
+        // computed with a separate keccak and ABI encoding that follows Seaport's
+        // order hashing and bulk order tree, rather than this crate
+        assert_eq!(
+            format!(
+                "{:x}",
+                hash_structured_data((&domain, &order_from(offerer, 1))).unwrap()
+            ),
+            "757f0f0f0e0cbf9ac16eaaa020acbcc0efdbf2de9ed2ee3388eac3f674f0434d"
+        );
+        // three orders and an empty one as padding
+        assert_eq!(
+            format!("{:x}", bulk.levels[2][0]),
+            "3858c28e4c7ad92dc2d4eec2151cbff72a4c6ea3d407d1d10e78ac65b80dc4b3"
+        );
+        assert_eq!(
+            format!("{:x}", bulk.digest(&domain)),
+            "6a57ba822b08578234f0e57b1304347591f8b9996f515f07ef77614bd5dd1e15"
+        );
+    }
+
+    #[test]
+    fn it_signs_bulk_orders_with_proofs() {
+        let bulk = BulkOrder::new((0..3).map(order).collect()).unwrap();
+        let domain = seaport_domain(1u64);
+        let signatures = bulk.sign(&domain, &test_key());
+
+        assert_eq!(
+            bulk.message_types()["BulkOrder"][0].type_,
+            "OrderComponents[2][2]"
+        );
+        for (index, encoded) in signatures.iter().enumerate() {
+            assert_eq!(encoded.len(), 65 + 3 + 32 * 2);
+            assert_eq!(encoded[65..68], [0, 0, index as u8]);
+
+            // Seaport's _computeBulkOrderProof
+            let mut root = bulk.orders()[index].struct_hash();
+            for (level, sibling) in encoded[68..].chunks(32).enumerate() {
+                root = if (index >> level) & 1 == 1 {
+                    keccak([sibling, &root.0[..]].concat())
+                } else {
+                    keccak([&root.0[..], sibling].concat())
+                };
+            }
+            assert_eq!(root, bulk.levels[2][0]);
+
+            let signature = Signature::from_bytes(&encoded[..65]).unwrap();
+            assert_eq!(
+                recover(&bulk.digest(&domain), &signature).unwrap(),
+                address(&test_key())
+            );
+        }
+    }
+}
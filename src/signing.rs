@@ -1,5 +1,73 @@
+use crate::error::{ErrorKind, Result};
+use ethereum_types::{Address, H256};
 use hex::{self, encode};
-use secp256k1::{Message, Secp256k1, SecretKey};
+use keccak_hash::keccak;
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, PublicKey, SecretKey, SECP256K1};
+use std::fmt;
+use std::str::FromStr;
+
+/// half the secp256k1 curve order, signatures with a larger `s` are malleable
+const SECP256K1_HALF_ORDER: [u8; 32] = [
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x5d, 0x57, 0x6e, 0x73, 0x57, 0xa4, 0x50, 0x1d, 0xdf, 0xe9, 0x2f, 0x46, 0x68, 0x1b, 0x20, 0xa0,
+];
+
+/// A recoverable ECDSA signature in Ethereum's `r || s || v` layout, `v` is 27 or 28
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    pub r: H256,
+    pub s: H256,
+    pub v: u8,
+}
+
+impl Signature {
+    pub fn to_bytes(&self) -> [u8; 65] {
+        let mut bytes = [0u8; 65];
+        bytes[..32].copy_from_slice(&self.r.0);
+        bytes[32..64].copy_from_slice(&self.s.0);
+        bytes[64] = self.v;
+        bytes
+    }
+
+    /// accepts `v` as 27/28 or as the raw recovery id 0/1
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != 65 {
+            Err(ErrorKind::InvalidSignature(format!(
+                "expected 65 bytes, got {}",
+                bytes.len()
+            )))?;
+        }
+        let v = match bytes[64] {
+            v @ 0..=1 => v + 27,
+            v @ 27..=28 => v,
+            v => Err(ErrorKind::InvalidSignature(format!("invalid v {}", v)))?,
+        };
+        Ok(Signature {
+            r: H256::from(&bytes[..32]),
+            s: H256::from(&bytes[32..64]),
+            v,
+        })
+    }
+}
+
+/// the 130 hex characters of `r || s || v`, without a 0x prefix
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", encode(self.to_bytes()))
+    }
+}
+
+impl FromStr for Signature {
+    type Err = crate::error::Error;
+
+    fn from_str(hex: &str) -> Result<Self> {
+        let hex = hex.strip_prefix("0x").unwrap_or(hex);
+        let bytes =
+            hex::decode(hex).map_err(|err| ErrorKind::InvalidSignature(format!("{}", err)))?;
+        Signature::from_bytes(&bytes)
+    }
+}
 
 pub fn sign_message(message_hex: &str, private_key_uint: u32) -> String {
     let message = hex::decode(message_hex).expect("Decoding failed");
@@ -10,25 +78,65 @@ pub fn sign_message(message_hex: &str, private_key_uint: u32) -> String {
 
     let secret_key =
         SecretKey::from_slice(&private_key_bytes).expect("32 bytes, within curve order");
-    let secp = Secp256k1::new();
 
     let message = Message::from_digest_slice(&message).expect("32 bytes");
 
-    let sig = secp.sign_ecdsa_recoverable(&message, &secret_key);
+    sign_digest(&message, &secret_key).to_string()
+}
+
+/// signs an already hashed EIP-712 digest, e.g. the output of `hash_structured_data`
+pub fn sign_hash(hash: &H256, secret_key: &SecretKey) -> String {
+    sign(hash, secret_key).to_string()
+}
+
+/// like `sign_hash`, returning the signature's parts
+pub fn sign(hash: &H256, secret_key: &SecretKey) -> Signature {
+    sign_digest(&Message::from_digest(hash.0), secret_key)
+}
 
+fn sign_digest(message: &Message, secret_key: &SecretKey) -> Signature {
+    let sig = SECP256K1.sign_ecdsa_recoverable(message, secret_key);
     let (rec_id, signature_bytes) = sig.serialize_compact();
 
-    // Convert to hex
-    let signature_hex = encode(signature_bytes); // Use 'encode' from the 'hex' crate
+    Signature {
+        r: H256::from(&signature_bytes[..32]),
+        s: H256::from(&signature_bytes[32..]),
+        v: 27 + rec_id.to_i32() as u8,
+    }
+}
 
-    if rec_id.to_i32() == 0 {
-        // append 27 in hex to the signature_hex
-        let signature_hex = format!("{}{:x}", signature_hex, 27);
-        return signature_hex;
-    } else {
-        let signature_hex = format!("{}{:x}", signature_hex, 29);
-        return signature_hex;
+/// the address that signed `hash`, rejecting malleable signatures with a high `s`
+/// just like OpenZeppelin's `ECDSA.recover`
+pub fn recover(hash: &H256, signature: &Signature) -> Result<Address> {
+    if signature.s.0 > SECP256K1_HALF_ORDER {
+        Err(ErrorKind::InvalidSignature(
+            "s is in the upper half order".into(),
+        ))?;
     }
+    let rec_id = RecoveryId::from_i32(i32::from(signature.v) - 27)
+        .map_err(|err| ErrorKind::InvalidSignature(format!("{}", err)))?;
+    let signature = RecoverableSignature::from_compact(&signature.to_bytes()[..64], rec_id)
+        .map_err(|err| ErrorKind::InvalidSignature(format!("{}", err)))?;
+    let public_key = SECP256K1
+        .recover_ecdsa(&Message::from_digest(hash.0), &signature)
+        .map_err(|err| ErrorKind::InvalidSignature(format!("{}", err)))?;
+    Ok(public_key_address(&public_key))
+}
+
+/// the Ethereum address of `secret_key`
+pub fn address(secret_key: &SecretKey) -> Address {
+    public_key_address(&PublicKey::from_secret_key(SECP256K1, secret_key))
+}
+
+fn public_key_address(public_key: &PublicKey) -> Address {
+    let hash = keccak(&public_key.serialize_uncompressed()[1..]);
+    Address::from(&hash.0[12..])
+}
+
+/// the key tests sign with
+#[cfg(test)]
+pub(crate) fn test_key() -> SecretKey {
+    SecretKey::from_slice(&[0x11; 32]).unwrap()
 }
 
 #[cfg(test)]
@@ -43,4 +151,46 @@ mod tests {
         let signature = sign_message(message, private_key);
         assert_eq!(signature, expected_signature);
     }
+
+    #[test]
+    fn it_appends_v_27_or_28() {
+        let message = "77915d20c811f39572463a234db9b776d518d07d9682a825be0d79752745a4c7";
+        let vs = (1..=16_u32)
+            .map(|private_key| sign_message(message, private_key)[128..].to_string())
+            .collect::<Vec<_>>();
+        assert!(vs.iter().all(|v| v == "1b" || v == "1c"));
+        assert!(vs.iter().any(|v| v == "1c"), "some key has recovery id 1");
+    }
+
+    #[test]
+    fn it_recovers_the_signer() {
+        let secret_key = test_key();
+        // recovery ids 0 and 1 map to v = 27 and v = 28
+        for byte in 0..4u8 {
+            let hash = keccak([byte]);
+            let signature = sign(&hash, &secret_key);
+            assert!(signature.v == 27 || signature.v == 28);
+            assert_eq!(recover(&hash, &signature).unwrap(), address(&secret_key));
+            assert_eq!(
+                signature.to_string().parse::<Signature>().unwrap(),
+                signature
+            );
+        }
+        assert_eq!(
+            format!("{:x}", address(&secret_key)),
+            "19e7e376e7c213b7e7e7e46cc70a5dd086daff2a"
+        );
+    }
+
+    #[test]
+    fn it_rejects_malformed_signatures() {
+        let secret_key = test_key();
+        let hash = keccak("message");
+        let mut signature = sign(&hash, &secret_key);
+        signature.s = H256::from(&[0xff; 32][..]);
+
+        assert!(recover(&hash, &signature).is_err());
+        assert!("0x1234".parse::<Signature>().is_err());
+        assert!(Signature::from_bytes(&[0; 65][..64]).is_err());
+    }
 }
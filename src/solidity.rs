@@ -0,0 +1,570 @@
+//! Solidity code generation and struct parsing
+use crate::eip712::{EIP712Domain, FieldType, MessageTypes};
+use crate::encode::{encode_type, sorted_dependencies, type_hash};
+use crate::error::{ErrorKind, Result};
+use crate::parser::{Parser, Type};
+use lunarity_lexer::{Lexer, Token};
+use rustc_hex::ToHex;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use toolshed::Arena;
+
+/// Generates Solidity source for `primary_type` and its dependencies: struct
+/// definitions, `*_TYPEHASH` constants, `_hash*` functions mirroring
+/// `encodeData`, and an OpenZeppelin `EIP712` based verifier contract for the
+/// given domain.
+pub fn generate_solidity(
+    message_types: &MessageTypes,
+    primary_type: &str,
+    domain: &EIP712Domain,
+) -> Result<String> {
+    let parser = Parser::new();
+    let deps = sorted_dependencies(primary_type, message_types)?;
+    // OpenZeppelin's EIP712 never hashes a salt, its separator would differ from the signed one
+    if domain.salt.is_some() {
+        Err(ErrorKind::UnknownField(
+            "salt".to_owned(),
+            "EIP712Domain".to_owned(),
+        ))?;
+    }
+    // OpenZeppelin's EIP712 always hashes a version
+    let version = domain
+        .version
+        .as_deref()
+        .ok_or_else(|| ErrorKind::MissingField("version".to_owned(), "EIP712Domain".to_owned()))?;
+    let mut out = String::new();
+
+    writeln!(out, "// SPDX-License-Identifier: MIT").unwrap();
+    writeln!(out, "// Generated by eip_712_utils, do not edit.").unwrap();
+    writeln!(out, "pragma solidity ^0.8.20;").unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "import {{EIP712}} from \"@openzeppelin/contracts/utils/cryptography/EIP712.sol\";"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "import {{ECDSA}} from \"@openzeppelin/contracts/utils/cryptography/ECDSA.sol\";"
+    )
+    .unwrap();
+
+    for dep in &deps {
+        writeln!(out).unwrap();
+        writeln!(out, "struct {} {{", dep).unwrap();
+        for field in &message_types[*dep] {
+            writeln!(out, "    {} {};", field.type_, field.name).unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+    }
+
+    for dep in &deps {
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "// keccak256(\"{}\")",
+            encode_type(dep, message_types)?
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "bytes32 constant {} = 0x{};",
+            type_hash_constant(dep),
+            type_hash(dep, message_types)?.to_hex::<String>()
+        )
+        .unwrap();
+    }
+
+    let mut hash_functions = vec![];
+    let mut array_helpers = BTreeMap::new();
+    for dep in &deps {
+        let mut fields = vec![format!("                {}", type_hash_constant(dep))];
+        for field in &message_types[*dep] {
+            let type_ = parser.parse_type(&field.type_)?;
+            let expr = hash_expression(
+                &type_,
+                &format!("data.{}", field.name),
+                message_types,
+                &mut array_helpers,
+            )?;
+            fields.push(format!("                {}", expr));
+        }
+        hash_functions.push(format!(
+            "    function _hash{name}({name} memory data) internal pure returns (bytes32) {{\n\
+             \x20       return keccak256(\n\
+             \x20           abi.encode(\n\
+             {fields}\n\
+             \x20           )\n\
+             \x20       );\n\
+             \x20   }}\n",
+            name = dep,
+            fields = fields.join(",\n"),
+        ));
+    }
+    hash_functions.extend(array_helpers.into_values());
+
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "/// @dev verifies signatures made for chainId {} and verifyingContract 0x{}",
+        domain.chain_id,
+        domain.verifying_contract.to_hex::<String>()
+    )
+    .unwrap();
+    writeln!(out, "contract {}Verifier is EIP712 {{", primary_type).unwrap();
+    writeln!(
+        out,
+        "    constructor() EIP712(\"{}\", \"{}\") {{}}",
+        escape_string(&domain.name),
+        escape_string(version)
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "    function hash{name}({name} memory data) public view returns (bytes32) {{\n\
+         \x20       return _hashTypedDataV4(_hash{name}(data));\n\
+         \x20   }}\n\
+         \n\
+         \x20   function verify{name}(\n\
+         \x20       {name} memory data,\n\
+         \x20       bytes memory signature,\n\
+         \x20       address signer\n\
+         \x20   ) public view returns (bool) {{\n\
+         \x20       return ECDSA.recover(hash{name}(data), signature) == signer;\n\
+         \x20   }}",
+        name = primary_type
+    )
+    .unwrap();
+    for function in hash_functions {
+        writeln!(out).unwrap();
+        write!(out, "{}", function).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+
+    Ok(out)
+}
+
+/// Parses the `struct` declarations in Solidity `source` into `MessageTypes`,
+/// keeping the declared field order. Everything outside of struct bodies is
+/// skipped; fields of a declared `enum` type are encoded as `uint8` and
+/// qualified names like `Lib.Person` resolve to `Person`.
+pub fn parse_solidity_structs(source: &str) -> Result<MessageTypes> {
+    let arena = Arena::new();
+    let mut lexer = Lexer::new(&arena, source);
+    let mut structs = vec![];
+    let mut enums = HashSet::new();
+
+    loop {
+        match lexer.token {
+            Token::EndOfProgram => break,
+            Token::UnexpectedToken | Token::UnexpectedEndOfProgram => {
+                return Err(unexpected(&lexer, "solidity source"))
+            }
+            Token::KeywordPragma => {
+                lexer.read_pragma();
+            }
+            Token::DeclarationEnum => {
+                lexer.consume();
+                if lexer.token == Token::Identifier {
+                    enums.insert(lexer.token_as_str().to_owned());
+                }
+            }
+            Token::DeclarationStruct => {
+                lexer.consume();
+                structs.push(parse_struct(&mut lexer)?);
+                continue;
+            }
+            _ => {}
+        }
+        lexer.consume();
+    }
+
+    let names = structs
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<HashSet<_>>();
+    let parser = Parser::new();
+    let mut message_types = MessageTypes::new();
+    for (name, fields) in structs {
+        let mut resolved = vec![];
+        for (field_name, type_) in fields {
+            let type_ = parser.parse_type(&type_)?;
+            let type_ = resolve_type(type_, &enums, &names)
+                .map_err(|unknown| ErrorKind::UnknownType(field_name.clone(), unknown))?;
+            resolved.push(FieldType {
+                name: field_name,
+                type_: type_.into(),
+            });
+        }
+        message_types.insert(name, resolved);
+    }
+
+    Ok(message_types)
+}
+
+/// parses `Name { type name; ... }` into the struct name and its raw field types,
+/// the lexer is expected to be right after the `struct` keyword
+fn parse_struct(lexer: &mut Lexer) -> Result<(String, Vec<(String, String)>)> {
+    let name = expect(lexer, Token::Identifier, "struct")?;
+    let context = format!("struct {}", name);
+    expect(lexer, Token::BraceOpen, &context)?;
+
+    let mut fields = vec![];
+    while lexer.token != Token::BraceClose {
+        let mut type_ = match lexer.token {
+            Token::Identifier => {
+                let mut path = expect(lexer, Token::Identifier, &context)?;
+                while lexer.token == Token::Accessor {
+                    lexer.consume();
+                    path = expect(lexer, Token::Identifier, &context)?;
+                }
+                path
+            }
+            Token::TypeAddress
+            | Token::TypeBool
+            | Token::TypeString
+            | Token::TypeBytes
+            | Token::TypeByte
+            | Token::TypeInt
+            | Token::TypeUint => {
+                let type_ = lexer.token_as_str().to_owned();
+                lexer.consume();
+                type_
+            }
+            _ => return Err(unexpected(lexer, &context)),
+        };
+        while lexer.token == Token::BracketOpen {
+            lexer.consume();
+            type_.push('[');
+            if lexer.token == Token::LiteralInteger {
+                type_.push_str(lexer.token_as_str());
+                lexer.consume();
+            }
+            expect(lexer, Token::BracketClose, &context)?;
+            type_.push(']');
+        }
+        let field_name = expect(lexer, Token::Identifier, &context)?;
+        expect(lexer, Token::Semicolon, &context)?;
+        fields.push((field_name, type_));
+    }
+    lexer.consume();
+
+    Ok((name, fields))
+}
+
+fn expect(lexer: &mut Lexer, token: Token, context: &str) -> Result<String> {
+    if lexer.token != token {
+        return Err(unexpected(lexer, context));
+    }
+    let value = lexer.token_as_str().to_owned();
+    lexer.consume();
+    Ok(value)
+}
+
+fn unexpected(lexer: &Lexer, context: &str) -> crate::error::Error {
+    ErrorKind::UnexpectedToken(lexer.token_as_str().to_owned(), context.to_owned()).into()
+}
+
+/// maps enum types to `uint8`, fails with the type name if it's neither a
+/// declared struct nor an enum
+fn resolve_type(
+    type_: Type,
+    enums: &HashSet<String>,
+    structs: &HashSet<String>,
+) -> ::std::result::Result<Type, String> {
+    match type_ {
+        Type::Custom(name) if enums.contains(&name) => Ok(Type::Uint(8)),
+        Type::Custom(name) if !structs.contains(&name) => Err(name),
+        Type::Array { inner, length } => Ok(Type::Array {
+            inner: Box::new(resolve_type(*inner, enums, structs)?),
+            length,
+        }),
+        type_ => Ok(type_),
+    }
+}
+
+/// `NFTData` => `NFT_DATA_TYPEHASH`
+fn type_hash_constant(type_name: &str) -> String {
+    let chars = type_name.chars().collect::<Vec<_>>();
+    let mut constant = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        let boundary = i > 0
+            && c.is_uppercase()
+            && (!chars[i - 1].is_uppercase()
+                || chars.get(i + 1).is_some_and(|next| next.is_lowercase()));
+        if boundary && chars[i - 1] != '_' {
+            constant.push('_');
+        }
+        constant.extend(c.to_uppercase());
+    }
+    constant + "_TYPEHASH"
+}
+
+/// the suffix of the `_hash*` helper for the given array type, e.g. `PersonArray2`
+fn helper_suffix(type_: &Type) -> String {
+    match type_ {
+        Type::Address => "Address".into(),
+        Type::Uint(bits) => format!("Uint{}", bits),
+        Type::Int(bits) => format!("Int{}", bits),
+        Type::String => "String".into(),
+        Type::Bool => "Bool".into(),
+        Type::Bytes => "Bytes".into(),
+        Type::Byte(len) => format!("Bytes{}", len),
+        Type::Custom(name) => name.clone(),
+        Type::Array { inner, length } => match length {
+            None => format!("{}Array", helper_suffix(inner)),
+            Some(length) => format!("{}Array{}", helper_suffix(inner), length),
+        },
+    }
+}
+
+fn is_atomic(type_: &Type) -> bool {
+    matches!(
+        type_,
+        Type::Address | Type::Uint(_) | Type::Int(_) | Type::Bool | Type::Byte(_)
+    )
+}
+
+/// the Solidity expression that encodes `expr` of `type_` into a single word,
+/// adding the array helpers it needs to `helpers`
+fn hash_expression(
+    type_: &Type,
+    expr: &str,
+    message_types: &MessageTypes,
+    helpers: &mut BTreeMap<String, String>,
+) -> Result<String> {
+    let encoded = match type_ {
+        _ if is_atomic(type_) => expr.to_owned(),
+        Type::String => format!("keccak256(bytes({}))", expr),
+        Type::Bytes => format!("keccak256({})", expr),
+        Type::Custom(name) if message_types.contains_key(name) => {
+            format!("_hash{}({})", name, expr)
+        }
+        Type::Array { inner, .. } if is_atomic(inner) => {
+            format!("keccak256(abi.encodePacked({}))", expr)
+        }
+        Type::Array { inner, .. } => {
+            let name = format!("_hash{}", helper_suffix(type_));
+            if !helpers.contains_key(&name) {
+                let item = hash_expression(inner, "items[i]", message_types, helpers)?;
+                let function = format!(
+                    "    function {name}({type_} memory items) internal pure returns (bytes32) {{\n\
+                     \x20       bytes32[] memory hashes = new bytes32[](items.length);\n\
+                     \x20       for (uint256 i = 0; i < items.length; i++) {{\n\
+                     \x20           hashes[i] = {item};\n\
+                     \x20       }}\n\
+                     \x20       return keccak256(abi.encodePacked(hashes));\n\
+                     \x20   }}\n",
+                    name = name,
+                    type_ = type_,
+                    item = item,
+                );
+                helpers.insert(name.clone(), function);
+            }
+            format!("{}({})", name, expr)
+        }
+        _ => Err(ErrorKind::UnknownType(
+            expr.to_owned(),
+            format!("{}", type_),
+        ))?,
+    };
+    Ok(encoded)
+}
+
+fn escape_string(string: &str) -> String {
+    let mut escaped = String::new();
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_ascii() && !c.is_ascii_control() => escaped.push(c),
+            // solidity string literals are ASCII only, anything else is escaped
+            c => {
+                let mut buf = [0u8; 4];
+                for byte in c.encode_utf8(&mut buf).bytes() {
+                    write!(escaped, "\\x{:02x}", byte).unwrap();
+                }
+            }
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eip712::FieldType;
+    use ethereum_types::H256;
+    use std::collections::HashMap;
+
+    fn field(name: &str, type_: &str) -> FieldType {
+        FieldType {
+            name: name.into(),
+            type_: type_.into(),
+        }
+    }
+
+    #[test]
+    fn it_generates_nft_data_verifier() {
+        let mut types = HashMap::new();
+        types.insert(
+            "NFTData".to_string(),
+            vec![
+                field("tokenId", "uint256"),
+                field("amount", "uint256"),
+                field("to", "address"),
+                field("nonce", "uint256"),
+            ],
+        );
+        let domain = EIP712Domain::new(
+            "AionRisingNFTs",
+            "0.0.1",
+            "0x7A69",
+            "0x037eDa3aDB1198021A9b2e88C22B464fD38db3f3",
+        );
+
+        assert_eq!(
+            generate_solidity(&types, "NFTData", &domain).unwrap(),
+            include_str!("../testdata/solidity/nft_data.sol")
+        );
+    }
+
+    #[test]
+    fn it_rejects_salted_domains() {
+        let mut types = HashMap::new();
+        types.insert("NFTData".to_string(), vec![field("tokenId", "uint256")]);
+        let mut domain = EIP712Domain::new(
+            "AionRisingNFTs",
+            "0.0.1",
+            "0x7A69",
+            "0x037eDa3aDB1198021A9b2e88C22B464fD38db3f3",
+        );
+        domain.salt = Some(H256::from(&[0x11; 32][..]));
+
+        assert_eq!(
+            generate_solidity(&types, "NFTData", &domain)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::UnknownField("salt".into(), "EIP712Domain".into())
+        );
+    }
+
+    #[test]
+    fn it_generates_nested_structs_and_arrays() {
+        let mut types = HashMap::new();
+        types.insert(
+            "Person".to_string(),
+            vec![field("name", "string"), field("wallets", "address[]")],
+        );
+        types.insert(
+            "Mail".to_string(),
+            vec![
+                field("from", "Person"),
+                field("to", "Person[]"),
+                field("contents", "string"),
+                field("attachments", "bytes[2]"),
+            ],
+        );
+        let domain = EIP712Domain {
+            name: "Ether \"Mail\"".into(),
+            version: Some("1".into()),
+            chain_id: 1.into(),
+            verifying_contract: "CcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC".parse().unwrap(),
+            salt: None,
+        };
+
+        assert_eq!(
+            generate_solidity(&types, "Mail", &domain).unwrap(),
+            include_str!("../testdata/solidity/mail.sol")
+        );
+    }
+
+    #[test]
+    fn it_parses_generated_structs_back() {
+        let types = parse_solidity_structs(include_str!("../testdata/solidity/mail.sol")).unwrap();
+
+        assert_eq!(types.len(), 2);
+        assert_eq!(
+            types["Mail"],
+            vec![
+                field("from", "Person"),
+                field("to", "Person[]"),
+                field("contents", "string"),
+                field("attachments", "bytes[2]"),
+            ]
+        );
+        assert_eq!(
+            encode_type("Mail", &types).unwrap(),
+            "Mail(Person from,Person[] to,string contents,bytes[2] attachments)\
+             Person(string name,address[] wallets)"
+        );
+    }
+
+    #[test]
+    fn it_parses_structs_inside_contracts() {
+        let source = r#"
+            pragma solidity ^0.8.0;
+
+            library Orders {
+                enum Side { Buy, Sell }
+
+                /* an order */
+                struct Order {
+                    address maker; // who signs
+                    Side side;
+                    uint amount;
+                    Orders.Asset[2][] assets;
+                }
+
+                struct Asset {
+                    bytes32 id;
+                    int64 weight;
+                }
+
+                function hash(Order memory order) internal pure returns (bytes32) {
+                    return keccak256(abi.encode(order.maker));
+                }
+            }
+        "#;
+        let types = parse_solidity_structs(source).unwrap();
+
+        assert_eq!(
+            types["Order"],
+            vec![
+                field("maker", "address"),
+                field("side", "uint8"),
+                field("amount", "uint256"),
+                field("assets", "Asset[2][]"),
+            ]
+        );
+        assert_eq!(
+            types["Asset"],
+            vec![field("id", "bytes32"), field("weight", "int64")]
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_struct_types() {
+        let source = "struct Mail { Person from; string contents; }";
+
+        assert_eq!(
+            parse_solidity_structs(source).unwrap_err().kind(),
+            ErrorKind::UnknownType("from".into(), "Person".into())
+        );
+        assert_eq!(
+            parse_solidity_structs("struct Mail { mapping(address => bool) seen; }")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::UnexpectedToken("mapping".into(), "struct Mail".into())
+        );
+    }
+
+    #[test]
+    fn it_names_type_hash_constants() {
+        assert_eq!(type_hash_constant("NFTData"), "NFT_DATA_TYPEHASH");
+        assert_eq!(type_hash_constant("PermitSingle"), "PERMIT_SINGLE_TYPEHASH");
+        assert_eq!(type_hash_constant("Person"), "PERSON_TYPEHASH");
+    }
+}
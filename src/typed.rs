@@ -0,0 +1,632 @@
+//! Typed EIP712 structs
+use crate::eip712::{EIP712Domain, FieldType, MessageTypes};
+use crate::encode::{encode_type, hash_typed_message};
+use crate::error::{Error, ErrorKind, Result};
+use ethereum_types::{Address, H256, U256};
+use keccak_hash::keccak;
+use std::collections::HashMap;
+
+/// A Rust type describing an EIP-712 struct, implemented by hand or through
+/// `#[derive(Eip712)]` (requires the `derive` feature).
+pub trait Eip712Struct {
+    /// the EIP-712 type name, e.g. `NFTData`
+    fn type_name() -> String;
+
+    /// the struct's own fields, in declaration order
+    fn fields() -> Vec<FieldType>;
+
+    /// inserts every struct type referenced by the fields into `types`
+    fn add_dependencies(types: &mut MessageTypes);
+
+    /// the `encodeData` of this value: one 32-byte word per field
+    fn encode_data(&self) -> Vec<H256>;
+
+    /// inserts this type and its dependencies into `types`
+    fn add_types(types: &mut MessageTypes) {
+        if types.contains_key(&Self::type_name()) {
+            return;
+        }
+        types.insert(Self::type_name(), Self::fields());
+        Self::add_dependencies(types);
+    }
+
+    /// the MessageTypes of this struct and its dependencies
+    fn message_types() -> MessageTypes {
+        let mut types = HashMap::new();
+        Self::add_types(&mut types);
+        types
+    }
+
+    /// the `encodeType` string, e.g. `NFTData(uint256 tokenId,...)`
+    fn encode_type() -> String {
+        encode_type(&Self::type_name(), &Self::message_types())
+            .expect("add_types inserts the type itself; qed")
+    }
+
+    /// keccak256 of `encode_type`
+    fn type_hash() -> H256 {
+        keccak(Self::encode_type())
+    }
+
+    /// the `hashStruct` of this value
+    fn struct_hash(&self) -> H256 {
+        let words = self.encode_data();
+        let mut encoded = Vec::with_capacity(32 * (words.len() + 1));
+        encoded.extend_from_slice(&Self::type_hash());
+        for word in words {
+            encoded.extend_from_slice(&word);
+        }
+        keccak(encoded)
+    }
+}
+
+/// A Rust type usable as the value of an EIP-712 field
+pub trait Eip712Type {
+    /// the Solidity type name, e.g. `uint256` or `Person[]`
+    fn field_type() -> String;
+
+    /// inserts every struct type this type references into `types`
+    fn add_field_types(_types: &mut MessageTypes) {}
+
+    /// the 32-byte word this value contributes to its parent's `encodeData`
+    fn encode_word(&self) -> H256;
+}
+
+/// An integer `Eip712Type`. A derived field of this type may override its
+/// Solidity type with `#[eip712(type = "...")]`, as long as the new type has
+/// the same signedness and is at least as wide.
+pub trait Eip712Integer: Eip712Type {
+    const BITS: usize;
+    const SIGNED: bool;
+}
+
+impl<T: Eip712Struct> Eip712Type for T {
+    fn field_type() -> String {
+        <T as Eip712Struct>::type_name()
+    }
+
+    fn add_field_types(types: &mut MessageTypes) {
+        <T as Eip712Struct>::add_types(types)
+    }
+
+    fn encode_word(&self) -> H256 {
+        self.struct_hash()
+    }
+}
+
+impl Eip712Type for Address {
+    fn field_type() -> String {
+        "address".into()
+    }
+
+    fn encode_word(&self) -> H256 {
+        let mut word = H256::zero();
+        word.0[12..].copy_from_slice(self);
+        word
+    }
+}
+
+impl Eip712Type for U256 {
+    fn field_type() -> String {
+        "uint256".into()
+    }
+
+    fn encode_word(&self) -> H256 {
+        H256::from(self)
+    }
+}
+
+impl Eip712Integer for U256 {
+    const BITS: usize = 256;
+    const SIGNED: bool = false;
+}
+
+/// A `uint8`, e.g. a Solidity enum. `u8` can't be an `Eip712Type` itself, as
+/// `Vec<u8>` is `bytes` rather than `uint8[]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Uint8(pub u8);
+
+impl From<u8> for Uint8 {
+    fn from(value: u8) -> Self {
+        Uint8(value)
+    }
+}
+
+impl Eip712Type for Uint8 {
+    fn field_type() -> String {
+        "uint8".into()
+    }
+
+    fn encode_word(&self) -> H256 {
+        H256::from(self.0 as u64)
+    }
+}
+
+impl Eip712Integer for Uint8 {
+    const BITS: usize = 8;
+    const SIGNED: bool = false;
+}
+
+/// A `uint48`, e.g. a Permit2 expiration. Built with `try_from`, which rejects
+/// values of 2^48 and above that the contract could never match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Uint48(u64);
+
+impl Uint48 {
+    pub const MAX: Uint48 = Uint48((1 << 48) - 1);
+
+    pub fn value(self) -> u64 {
+        self.0
+    }
+}
+
+impl TryFrom<u64> for Uint48 {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self> {
+        if value > Self::MAX.0 {
+            Err(ErrorKind::ValueOutOfRange(
+                value.to_string(),
+                "uint48".into(),
+            ))?;
+        }
+        Ok(Uint48(value))
+    }
+}
+
+impl From<u32> for Uint48 {
+    fn from(value: u32) -> Self {
+        Uint48(value.into())
+    }
+}
+
+impl Eip712Type for Uint48 {
+    fn field_type() -> String {
+        "uint48".into()
+    }
+
+    fn encode_word(&self) -> H256 {
+        H256::from(self.0)
+    }
+}
+
+impl Eip712Integer for Uint48 {
+    const BITS: usize = 48;
+    const SIGNED: bool = false;
+}
+
+/// A `uint160`, e.g. a Permit2 allowance. Built with `try_from`, which rejects
+/// values of 2^160 and above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Uint160(U256);
+
+impl Uint160 {
+    pub const MAX: Uint160 = Uint160(U256([u64::MAX, u64::MAX, u32::MAX as u64, 0]));
+
+    pub fn value(self) -> U256 {
+        self.0
+    }
+}
+
+impl TryFrom<U256> for Uint160 {
+    type Error = Error;
+
+    fn try_from(value: U256) -> Result<Self> {
+        if value > Self::MAX.0 {
+            Err(ErrorKind::ValueOutOfRange(
+                format!("{:#x}", value),
+                "uint160".into(),
+            ))?;
+        }
+        Ok(Uint160(value))
+    }
+}
+
+impl From<u64> for Uint160 {
+    fn from(value: u64) -> Self {
+        Uint160(value.into())
+    }
+}
+
+impl Eip712Type for Uint160 {
+    fn field_type() -> String {
+        "uint160".into()
+    }
+
+    fn encode_word(&self) -> H256 {
+        H256::from(self.0)
+    }
+}
+
+impl Eip712Integer for Uint160 {
+    const BITS: usize = 160;
+    const SIGNED: bool = false;
+}
+
+impl Eip712Type for H256 {
+    fn field_type() -> String {
+        "bytes32".into()
+    }
+
+    fn encode_word(&self) -> H256 {
+        *self
+    }
+}
+
+impl Eip712Type for bool {
+    fn field_type() -> String {
+        "bool".into()
+    }
+
+    fn encode_word(&self) -> H256 {
+        H256::from(*self as u64)
+    }
+}
+
+impl Eip712Type for String {
+    fn field_type() -> String {
+        "string".into()
+    }
+
+    fn encode_word(&self) -> H256 {
+        keccak(self)
+    }
+}
+
+/// `bytes`; `uint8` fields use `Uint8`
+impl Eip712Type for Vec<u8> {
+    fn field_type() -> String {
+        "bytes".into()
+    }
+
+    fn encode_word(&self) -> H256 {
+        keccak(self)
+    }
+}
+
+impl<T: Eip712Type> Eip712Type for Vec<T> {
+    fn field_type() -> String {
+        format!("{}[]", T::field_type())
+    }
+
+    fn add_field_types(types: &mut MessageTypes) {
+        T::add_field_types(types)
+    }
+
+    fn encode_word(&self) -> H256 {
+        encode_array(self)
+    }
+}
+
+impl<T: Eip712Type, const N: usize> Eip712Type for [T; N] {
+    fn field_type() -> String {
+        format!("{}[{}]", T::field_type(), N)
+    }
+
+    fn add_field_types(types: &mut MessageTypes) {
+        T::add_field_types(types)
+    }
+
+    fn encode_word(&self) -> H256 {
+        encode_array(self)
+    }
+}
+
+fn encode_array<T: Eip712Type>(items: &[T]) -> H256 {
+    let mut encoded = Vec::with_capacity(32 * items.len());
+    for item in items {
+        encoded.extend_from_slice(&item.encode_word());
+    }
+    keccak(encoded)
+}
+
+macro_rules! impl_uint {
+    ($($ty:ty => $name:expr),*) => {$(
+        impl Eip712Type for $ty {
+            fn field_type() -> String {
+                $name.into()
+            }
+
+            fn encode_word(&self) -> H256 {
+                let mut word = H256::zero();
+                let bytes = self.to_be_bytes();
+                word.0[32 - bytes.len()..].copy_from_slice(&bytes);
+                word
+            }
+        }
+
+        impl Eip712Integer for $ty {
+            const BITS: usize = <$ty>::BITS as usize;
+            const SIGNED: bool = false;
+        }
+    )*};
+}
+
+macro_rules! impl_int {
+    ($($ty:ty => $name:expr),*) => {$(
+        impl Eip712Type for $ty {
+            fn field_type() -> String {
+                $name.into()
+            }
+
+            // two's complement, sign-extended to 256 bits
+            fn encode_word(&self) -> H256 {
+                let mut word = if *self < 0 { H256::from([0xff; 32]) } else { H256::zero() };
+                let bytes = self.to_be_bytes();
+                word.0[32 - bytes.len()..].copy_from_slice(&bytes);
+                word
+            }
+        }
+
+        impl Eip712Integer for $ty {
+            const BITS: usize = <$ty>::BITS as usize;
+            const SIGNED: bool = true;
+        }
+    )*};
+}
+
+impl_uint!(u16 => "uint16", u32 => "uint32", u64 => "uint64", u128 => "uint128");
+impl_int!(i8 => "int8", i16 => "int16", i32 => "int32", i64 => "int64", i128 => "int128");
+
+impl EIP712Domain {
+    /// the domain separator: the `hashStruct` over the fields this domain sets,
+    /// leaving out a missing `version` and including the `salt`
+    pub fn separator(&self) -> H256 {
+        let mut types = HashMap::new();
+        types.insert("EIP712Domain".to_string(), self.field_types());
+        let encoded_type =
+            encode_type("EIP712Domain", &types).expect("the type was just inserted; qed");
+
+        let mut words = vec![keccak(encoded_type), self.name.encode_word()];
+        if let Some(version) = &self.version {
+            words.push(version.encode_word());
+        }
+        words.push(self.chain_id.encode_word());
+        words.push(self.verifying_contract.encode_word());
+        if let Some(salt) = self.salt {
+            words.push(salt.encode_word());
+        }
+        keccak(words.iter().flat_map(|word| word.0).collect::<Vec<_>>())
+    }
+}
+
+/// Data that can be hashed with `hash_structured_data`: either an untyped
+/// `EIP712` or a domain paired with an `Eip712Struct` message, e.g.
+/// `hash_structured_data((&domain, &message))`.
+pub trait StructuredData {
+    /// the hashStruct of the domain
+    fn domain_separator(&self) -> Result<H256>;
+
+    /// the hashStruct of the message
+    fn message_hash(&self) -> Result<H256>;
+
+    /// the final EIP-712 digest
+    fn digest(&self) -> Result<H256> {
+        Ok(hash_typed_message(
+            &self.domain_separator()?,
+            &self.message_hash()?,
+        ))
+    }
+}
+
+impl<T: Eip712Struct> StructuredData for (&EIP712Domain, &T) {
+    fn domain_separator(&self) -> Result<H256> {
+        Ok(self.0.separator())
+    }
+
+    fn message_hash(&self) -> Result<H256> {
+        Ok(self.1.struct_hash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eip712::EIP712;
+    use crate::encode::hash_structured_data;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    struct Person {
+        name: String,
+        wallet: Address,
+    }
+
+    struct Mail {
+        from: Person,
+        to: Person,
+        contents: String,
+    }
+
+    impl Eip712Struct for Person {
+        fn type_name() -> String {
+            "Person".into()
+        }
+
+        fn fields() -> Vec<FieldType> {
+            vec![
+                FieldType {
+                    name: "name".into(),
+                    type_: "string".into(),
+                },
+                FieldType {
+                    name: "wallet".into(),
+                    type_: "address".into(),
+                },
+            ]
+        }
+
+        fn add_dependencies(_types: &mut MessageTypes) {}
+
+        fn encode_data(&self) -> Vec<H256> {
+            vec![self.name.encode_word(), self.wallet.encode_word()]
+        }
+    }
+
+    impl Eip712Struct for Mail {
+        fn type_name() -> String {
+            "Mail".into()
+        }
+
+        fn fields() -> Vec<FieldType> {
+            vec![
+                FieldType {
+                    name: "from".into(),
+                    type_: "Person".into(),
+                },
+                FieldType {
+                    name: "to".into(),
+                    type_: "Person".into(),
+                },
+                FieldType {
+                    name: "contents".into(),
+                    type_: "string".into(),
+                },
+            ]
+        }
+
+        fn add_dependencies(types: &mut MessageTypes) {
+            <Person as Eip712Struct>::add_types(types);
+        }
+
+        fn encode_data(&self) -> Vec<H256> {
+            vec![
+                self.from.encode_word(),
+                self.to.encode_word(),
+                self.contents.encode_word(),
+            ]
+        }
+    }
+
+    fn address(hex: &str) -> Address {
+        Address::from_str(hex).unwrap()
+    }
+
+    fn mail() -> Mail {
+        Mail {
+            from: Person {
+                name: "Cow".into(),
+                wallet: address("CD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"),
+            },
+            to: Person {
+                name: "Bob".into(),
+                wallet: address("bBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"),
+            },
+            contents: "Hello, Bob!".into(),
+        }
+    }
+
+    fn domain() -> EIP712Domain {
+        EIP712Domain::new(
+            "Ether Mail",
+            "1",
+            "0x1",
+            "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC",
+        )
+    }
+
+    #[test]
+    fn it_encodes_type_with_dependencies() {
+        assert_eq!(
+            Mail::encode_type(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            format!("{:x}", Mail::type_hash()),
+            "a0cedeb2dc280ba39b857546d74f5549c3a1d7bdc2dd96bf881f76108e23dac2"
+        );
+    }
+
+    #[test]
+    fn it_hashes_typed_data() {
+        assert_eq!(
+            format!("{:x}", domain().separator()),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            format!("{:x}", mail().struct_hash()),
+            "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        );
+        assert_eq!(
+            format!("{:x}", hash_structured_data((&domain(), &mail())).unwrap()),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+    }
+
+    #[test]
+    fn it_matches_the_untyped_encoder() {
+        let message = json!({
+            "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+            "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+            "contents": "Hello, Bob!"
+        });
+        let mut data = EIP712::new(domain(), message, Some((Mail::type_name(), Mail::fields())));
+        data.add_type(Person::type_name(), Person::fields());
+
+        assert_eq!(
+            hash_structured_data(data).unwrap(),
+            hash_structured_data((&domain(), &mail())).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_hashes_salted_domains_like_the_untyped_encoder() {
+        let salted = domain().with_salt(H256::from(&[0xab; 32][..]));
+        let message = json!({
+            "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+            "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+            "contents": "Hello, Bob!"
+        });
+        let mut data = EIP712::new(
+            salted.clone(),
+            message,
+            Some((Mail::type_name(), Mail::fields())),
+        );
+        data.add_type(Person::type_name(), Person::fields());
+
+        assert_eq!(
+            hash_structured_data(data).unwrap(),
+            hash_structured_data((&salted, &mail())).unwrap()
+        );
+        assert_ne!(salted.separator(), domain().separator());
+    }
+
+    #[test]
+    fn it_encodes_primitives() {
+        assert_eq!(<Vec<[U256; 2]>>::field_type(), "uint256[2][]");
+        assert_eq!(true.encode_word(), H256::from(1));
+        assert_eq!(300u16.encode_word(), H256::from(300));
+        assert_eq!(Uint8::field_type(), "uint8");
+        assert_eq!(Uint8(2).encode_word(), U256::from(2).encode_word());
+        assert_eq!(
+            (-1i8).encode_word(),
+            H256::from([0xff; 32]),
+            "negative integers are sign-extended"
+        );
+        assert_eq!(
+            vec![1u8, 2, 3].encode_word(),
+            keccak([1u8, 2, 3]),
+            "bytes are hashed"
+        );
+        assert_eq!(
+            [U256::from(1), U256::from(2)].encode_word(),
+            keccak([H256::from(1).to_vec(), H256::from(2).to_vec()].concat())
+        );
+    }
+
+    #[test]
+    fn it_bounds_narrow_integers() {
+        assert_eq!(Uint48::try_from((1u64 << 48) - 1).unwrap(), Uint48::MAX);
+        assert_eq!(
+            Uint48::try_from(1u64 << 48).unwrap_err().kind(),
+            ErrorKind::ValueOutOfRange((1u64 << 48).to_string(), "uint48".into())
+        );
+        assert_eq!(Uint48::MAX.encode_word(), H256::from((1u64 << 48) - 1));
+
+        let max = U256::from(2).pow(160.into()) - 1;
+        assert_eq!(Uint160::try_from(max).unwrap(), Uint160::MAX);
+        assert_eq!(Uint160::MAX.value(), max);
+        assert!(Uint160::try_from(max + 1).is_err());
+        assert_eq!(Uint160::field_type(), "uint160");
+    }
+}
@@ -0,0 +1,209 @@
+//! TypeScript code generation
+use crate::eip712::{EIP712Domain, MessageTypes};
+use crate::encode::sorted_dependencies;
+use crate::error::Result;
+use crate::parser::{Parser, Type};
+use ethereum_types::U256;
+use rustc_hex::ToHex;
+use std::fmt::Write;
+
+/// Generates a TypeScript module for `primary_type` and its dependencies: the
+/// `domain`, `types` and `primaryType` constants (`as const`, so viem infers the
+/// message type), an interface per struct, and a `typedData` helper whose result
+/// can be passed to viem's `signTypedData`. `domain` and `types` also work with
+/// ethers' `signTypedData(domain, types, message)`, as `types` doesn't contain
+/// `EIP712Domain`.
+pub fn generate_typescript(
+    message_types: &MessageTypes,
+    primary_type: &str,
+    domain: &EIP712Domain,
+) -> Result<String> {
+    let parser = Parser::new();
+    let deps = sorted_dependencies(primary_type, message_types)?;
+    let mut out = String::new();
+
+    writeln!(out, "// Generated by eip_712_utils, do not edit.").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "export const domain = {{").unwrap();
+    writeln!(out, "  name: {},", string_literal(&domain.name)).unwrap();
+    if let Some(version) = &domain.version {
+        writeln!(out, "  version: {},", string_literal(version)).unwrap();
+    }
+    writeln!(out, "  chainId: {},", chain_id_literal(domain.chain_id)).unwrap();
+    writeln!(
+        out,
+        "  verifyingContract: \"0x{}\",",
+        domain.verifying_contract.to_hex::<String>()
+    )
+    .unwrap();
+    if let Some(salt) = domain.salt {
+        writeln!(out, "  salt: \"0x{}\",", salt.to_hex::<String>()).unwrap();
+    }
+    writeln!(out, "}} as const;").unwrap();
+
+    writeln!(out).unwrap();
+    writeln!(out, "export const types = {{").unwrap();
+    for dep in &deps {
+        writeln!(out, "  {}: [", dep).unwrap();
+        for field in &message_types[*dep] {
+            writeln!(
+                out,
+                "    {{ name: {}, type: {} }},",
+                string_literal(&field.name),
+                string_literal(&field.type_)
+            )
+            .unwrap();
+        }
+        writeln!(out, "  ],").unwrap();
+    }
+    writeln!(out, "}} as const;").unwrap();
+
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "export const primaryType = \"{}\" as const;",
+        primary_type
+    )
+    .unwrap();
+
+    for dep in &deps {
+        writeln!(out).unwrap();
+        writeln!(out, "export interface {} {{", dep).unwrap();
+        for field in &message_types[*dep] {
+            let type_ = parser.parse_type(&field.type_)?;
+            writeln!(out, "  {}: {};", field.name, typescript_type(&type_)).unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+    }
+
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "export function typedData(message: {}) {{\n  \
+         return {{ domain, types, primaryType, message }} as const;\n\
+         }}",
+        primary_type
+    )
+    .unwrap();
+
+    Ok(out)
+}
+
+/// the TypeScript type viem expects for values of `type_`: integers of up to 48
+/// bits fit into a `number`, wider ones are `bigint`s, short fixed size arrays
+/// are tuples
+fn typescript_type(type_: &Type) -> String {
+    match type_ {
+        Type::Uint(bits) | Type::Int(bits) if *bits <= 48 => "number".into(),
+        Type::Uint(_) | Type::Int(_) => "bigint".into(),
+        Type::Address | Type::Bytes | Type::Byte(_) => "`0x${string}`".into(),
+        Type::String => "string".into(),
+        Type::Bool => "boolean".into(),
+        Type::Custom(name) => name.clone(),
+        Type::Array { inner, length } => match length {
+            Some(length) if *length <= 8 => {
+                let item = typescript_type(inner);
+                format!("[{}]", vec![item; *length as usize].join(", "))
+            }
+            _ => format!("{}[]", typescript_type(inner)),
+        },
+    }
+}
+
+/// a `number` while it is a safe integer, a `bigint` literal otherwise
+fn chain_id_literal(chain_id: U256) -> String {
+    if chain_id <= U256::from((1u64 << 53) - 1) {
+        format!("{}", chain_id)
+    } else {
+        format!("{}n", chain_id)
+    }
+}
+
+fn string_literal(string: &str) -> String {
+    serde_json::to_string(string).expect("strings always serialize; qed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eip712::FieldType;
+    use std::collections::HashMap;
+
+    fn field(name: &str, type_: &str) -> FieldType {
+        FieldType {
+            name: name.into(),
+            type_: type_.into(),
+        }
+    }
+
+    #[test]
+    fn it_generates_nft_data_module() {
+        let mut types = HashMap::new();
+        types.insert(
+            "NFTData".to_string(),
+            vec![
+                field("tokenId", "uint256"),
+                field("amount", "uint256"),
+                field("to", "address"),
+                field("nonce", "uint256"),
+            ],
+        );
+        let domain = EIP712Domain::new(
+            "AionRisingNFTs",
+            "0.0.1",
+            "0x7A69",
+            "0x037eDa3aDB1198021A9b2e88C22B464fD38db3f3",
+        );
+
+        assert_eq!(
+            generate_typescript(&types, "NFTData", &domain).unwrap(),
+            include_str!("../testdata/typescript/nft_data.ts")
+        );
+    }
+
+    #[test]
+    fn it_generates_nested_structs_and_arrays() {
+        let mut types = HashMap::new();
+        types.insert(
+            "Person".to_string(),
+            vec![field("name", "string"), field("wallets", "address[]")],
+        );
+        types.insert(
+            "Mail".to_string(),
+            vec![
+                field("from", "Person"),
+                field("to", "Person[]"),
+                field("contents", "string"),
+                field("attachments", "bytes[2]"),
+                field("priority", "uint8"),
+            ],
+        );
+        let domain = EIP712Domain {
+            name: "Ether \"Mail\"".into(),
+            version: Some("1".into()),
+            chain_id: 1.into(),
+            verifying_contract: "CcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC".parse().unwrap(),
+            salt: Some([0xab; 32].into()),
+        };
+
+        assert_eq!(
+            generate_typescript(&types, "Mail", &domain).unwrap(),
+            include_str!("../testdata/typescript/mail.ts")
+        );
+    }
+
+    #[test]
+    fn it_maps_solidity_types() {
+        let parser = Parser::new();
+        let ts = |type_: &str| typescript_type(&parser.parse_type(type_).unwrap());
+
+        assert_eq!(ts("uint48"), "number");
+        assert_eq!(ts("int56"), "bigint");
+        assert_eq!(ts("bytes32"), "`0x${string}`");
+        assert_eq!(ts("bool[2][]"), "[boolean, boolean][]");
+        assert_eq!(
+            chain_id_literal(U256::max_value()).chars().last(),
+            Some('n')
+        );
+    }
+}
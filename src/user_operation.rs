@@ -0,0 +1,392 @@
+//! ERC-4337 user operations as hashed by EntryPoint v0.8, which signs
+//! `PackedUserOperation` with EIP-712
+use crate::eip712::{EIP712Domain, FieldType, MessageTypes};
+use crate::error::{ErrorKind, Result};
+use crate::nft_helpers::{generate_typed_data, MessageSchema};
+use crate::typed::{Eip712Struct, Eip712Type, StructuredData};
+use ethereum_types::{Address, H256, U256};
+use rustc_hex::ToHex;
+use serde_json::{json, Value};
+
+/// the address EntryPoint v0.8 is deployed at on every chain
+pub const ENTRY_POINT_V08_ADDRESS: &str = "4337084D9E255Ff0702461CF8895CE9E3b5Ff108";
+
+/// the first bytes of the `initCode` of an EIP-7702 account, padded with zeros
+/// to 20 bytes
+pub const EIP7702_MARKER: [u8; 2] = [0x77, 0x02];
+
+/// the domain of the EntryPoint at `entry_point`
+pub fn entry_point_domain<C: Into<U256>>(chain_id: C, entry_point: Address) -> EIP712Domain {
+    EIP712Domain::from_parts("ERC4337", "1", chain_id, entry_point)
+}
+
+/// the domain of EntryPoint v0.8 on `chain_id`
+pub fn entry_point_v08_domain<C: Into<U256>>(chain_id: C) -> EIP712Domain {
+    entry_point_domain(
+        chain_id,
+        ENTRY_POINT_V08_ADDRESS
+            .parse()
+            .expect("the address is valid hex; qed"),
+    )
+}
+
+/// two `uint128`s packed into one word, `high` first
+fn pack_u128s(high: u128, low: u128) -> H256 {
+    let mut word = H256::zero();
+    word.0[..16].copy_from_slice(&high.to_be_bytes());
+    word.0[16..].copy_from_slice(&low.to_be_bytes());
+    word
+}
+
+/// A user operation with its fields unpacked, as bundlers' RPC methods take it
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UserOperation {
+    pub sender: Address,
+    pub nonce: U256,
+    /// the factory deploying `sender`, for its first operation only, or
+    /// `EIP7702_MARKER` padded to 20 bytes for an EIP-7702 account
+    pub factory: Option<Address>,
+    pub factory_data: Vec<u8>,
+    pub call_data: Vec<u8>,
+    pub call_gas_limit: u128,
+    pub verification_gas_limit: u128,
+    pub pre_verification_gas: U256,
+    pub max_fee_per_gas: u128,
+    pub max_priority_fee_per_gas: u128,
+    pub paymaster: Option<Address>,
+    pub paymaster_verification_gas_limit: u128,
+    pub paymaster_post_op_gas_limit: u128,
+    pub paymaster_data: Vec<u8>,
+    /// not part of the hash
+    pub signature: Vec<u8>,
+    /// see `PackedUserOperation::eip7702_delegate`
+    pub eip7702_delegate: Option<Address>,
+}
+
+impl UserOperation {
+    /// packs the gas fields and concatenates `initCode` and `paymasterAndData`,
+    /// like `toPackedUserOperation` of the reference bundler
+    pub fn pack(&self) -> PackedUserOperation {
+        let init_code = match self.factory {
+            Some(factory) => [&factory.0[..], &self.factory_data].concat(),
+            None => vec![],
+        };
+        let paymaster_and_data = match self.paymaster {
+            Some(paymaster) => [
+                &paymaster.0[..],
+                &self.paymaster_verification_gas_limit.to_be_bytes(),
+                &self.paymaster_post_op_gas_limit.to_be_bytes(),
+                &self.paymaster_data,
+            ]
+            .concat(),
+            None => vec![],
+        };
+        PackedUserOperation {
+            sender: self.sender,
+            nonce: self.nonce,
+            init_code,
+            call_data: self.call_data.clone(),
+            account_gas_limits: pack_u128s(self.verification_gas_limit, self.call_gas_limit),
+            pre_verification_gas: self.pre_verification_gas,
+            gas_fees: pack_u128s(self.max_priority_fee_per_gas, self.max_fee_per_gas),
+            paymaster_and_data,
+            signature: self.signature.clone(),
+            eip7702_delegate: self.eip7702_delegate,
+        }
+    }
+
+    /// the `userOpHash` the account validates, see `PackedUserOperation::user_op_hash`
+    pub fn user_op_hash(&self, domain: &EIP712Domain) -> Result<H256> {
+        self.pack().user_op_hash(domain)
+    }
+}
+
+/// `PackedUserOperation(address sender,uint256 nonce,bytes initCode,bytes callData,bytes32 accountGasLimits,uint256 preVerificationGas,bytes32 gasFees,bytes paymasterAndData)`,
+/// the operation as passed to `handleOps`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PackedUserOperation {
+    pub sender: Address,
+    pub nonce: U256,
+    pub init_code: Vec<u8>,
+    pub call_data: Vec<u8>,
+    /// `verificationGasLimit` and `callGasLimit`
+    pub account_gas_limits: H256,
+    pub pre_verification_gas: U256,
+    /// `maxPriorityFeePerGas` and `maxFeePerGas`
+    pub gas_fees: H256,
+    pub paymaster_and_data: Vec<u8>,
+    /// not part of the hash
+    pub signature: Vec<u8>,
+    /// the code `sender` delegates to with EIP-7702, which the EntryPoint
+    /// reads from the chain; required when `init_code` starts with `EIP7702_MARKER`
+    pub eip7702_delegate: Option<Address>,
+}
+
+impl PackedUserOperation {
+    /// whether `init_code` marks `sender` as an EIP-7702 account
+    pub fn is_eip7702(&self) -> bool {
+        self.init_code.starts_with(&EIP7702_MARKER)
+    }
+
+    /// the `userOpHash` returned by the EntryPoint's `getUserOpHash`, i.e. the
+    /// EIP-712 digest of the operation under `domain`
+    pub fn user_op_hash(&self, domain: &EIP712Domain) -> Result<H256> {
+        if self.is_eip7702() && self.eip7702_delegate.is_none() {
+            Err(ErrorKind::MissingField(
+                "eip7702Delegate".into(),
+                Self::type_name(),
+            ))?;
+        }
+        (domain, self).digest()
+    }
+
+    /// the `initCode` the EntryPoint hashes: for an EIP-7702 account the
+    /// delegate followed by `init_code[20..]`
+    fn hashed_init_code(&self) -> Vec<u8> {
+        match self.eip7702_delegate {
+            Some(delegate) if self.is_eip7702() => {
+                let rest = self.init_code.get(20..).unwrap_or_default();
+                [&delegate.0[..], rest].concat()
+            }
+            _ => self.init_code.clone(),
+        }
+    }
+
+    /// the message as JSON, with the `initCode` of an EIP-7702 account
+    /// replaced the way the EntryPoint hashes it
+    pub fn message(&self) -> Value {
+        let bytes = |bytes: &[u8]| format!("0x{}", bytes.to_hex::<String>());
+        json!({
+            "sender": format!("0x{:x}", self.sender),
+            "nonce": format!("0x{:x}", self.nonce),
+            "initCode": bytes(&self.hashed_init_code()),
+            "callData": bytes(&self.call_data),
+            "accountGasLimits": format!("0x{:x}", self.account_gas_limits),
+            "preVerificationGas": format!("0x{:x}", self.pre_verification_gas),
+            "gasFees": format!("0x{:x}", self.gas_fees),
+            "paymasterAndData": bytes(&self.paymaster_and_data),
+        })
+    }
+
+    /// the typed data JSON to pass to `eth_signTypedData_v4`
+    pub fn typed_data(&self, domain: &EIP712Domain) -> Value {
+        let schema = MessageSchema::new(&Self::type_name(), Self::fields());
+        generate_typed_data(domain, &schema, self.message())
+            .expect("user operations always match their schema; qed")
+    }
+}
+
+impl Eip712Struct for PackedUserOperation {
+    fn type_name() -> String {
+        "PackedUserOperation".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType::new("sender", "address"),
+            FieldType::new("nonce", "uint256"),
+            FieldType::new("initCode", "bytes"),
+            FieldType::new("callData", "bytes"),
+            FieldType::new("accountGasLimits", "bytes32"),
+            FieldType::new("preVerificationGas", "uint256"),
+            FieldType::new("gasFees", "bytes32"),
+            FieldType::new("paymasterAndData", "bytes"),
+        ]
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.sender.encode_word(),
+            self.nonce.encode_word(),
+            self.hashed_init_code().encode_word(),
+            self.call_data.encode_word(),
+            self.account_gas_limits.encode_word(),
+            self.pre_verification_gas.encode_word(),
+            self.gas_fees.encode_word(),
+            self.paymaster_and_data.encode_word(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eip712::EIP712;
+    use crate::encode::hash_structured_data;
+
+    fn operation() -> UserOperation {
+        UserOperation {
+            sender: Address::from(&[0xaa; 20][..]),
+            nonce: 5.into(),
+            factory: Some(Address::from(&[0xfa; 20][..])),
+            factory_data: vec![0x01, 0x02],
+            call_data: vec![0xb6, 0x1d, 0x27, 0xf6],
+            call_gas_limit: 0x11,
+            verification_gas_limit: 0x22,
+            pre_verification_gas: 0x33.into(),
+            max_fee_per_gas: 0x44,
+            max_priority_fee_per_gas: 0x55,
+            paymaster: Some(Address::from(&[0x99; 20][..])),
+            paymaster_verification_gas_limit: 0x66,
+            paymaster_post_op_gas_limit: 0x77,
+            paymaster_data: vec![0xde, 0xad],
+            signature: vec![0xff; 65],
+            eip7702_delegate: None,
+        }
+    }
+
+    #[test]
+    fn it_uses_the_entry_point_type_hash() {
+        // UserOperationLib.PACKED_USEROP_TYPEHASH of EntryPoint v0.8
+        assert_eq!(
+            format!("{:x}", PackedUserOperation::type_hash()),
+            "29a0bca4af4be3421398da00295e58e6d7de38cb492214754cb6a47507dd6f8e"
+        );
+
+        let domain = entry_point_v08_domain(1u64);
+        assert_eq!(domain.name(), "ERC4337");
+        assert_eq!(domain.version(), Some("1"));
+        assert_eq!(
+            format!("{:x}", domain.verifying_contract()),
+            "4337084d9e255ff0702461cf8895ce9e3b5ff108"
+        );
+    }
+
+    #[test]
+    fn it_packs_the_gas_fields() {
+        let packed = operation().pack();
+
+        assert_eq!(packed.init_code, [&[0xfa; 20][..], &[0x01, 0x02]].concat());
+        assert_eq!(
+            format!("{:x}", packed.account_gas_limits),
+            format!("{:032x}{:032x}", 0x22, 0x11)
+        );
+        assert_eq!(
+            format!("{:x}", packed.gas_fees),
+            format!("{:032x}{:032x}", 0x55, 0x44)
+        );
+        assert_eq!(packed.paymaster_and_data.len(), 20 + 16 + 16 + 2);
+        assert_eq!(packed.paymaster_and_data[..20], [0x99; 20]);
+        assert_eq!(packed.paymaster_and_data[35], 0x66);
+        assert_eq!(packed.paymaster_and_data[51], 0x77);
+        assert_eq!(packed.paymaster_and_data[52..], [0xde, 0xad]);
+
+        let bare = UserOperation {
+            factory: None,
+            paymaster: None,
+            ..operation()
+        }
+        .pack();
+        assert!(bare.init_code.is_empty());
+        assert!(bare.paymaster_and_data.is_empty());
+    }
+
+    #[test]
+    fn it_hashes_like_the_json_encoder() {
+        let domain = entry_point_v08_domain(11_155_111u64);
+        let operation = operation();
+        let packed = operation.pack();
+        let typed_data = serde_json::from_value::<EIP712>(packed.typed_data(&domain)).unwrap();
+
+        assert_eq!(
+            hash_structured_data(typed_data).unwrap(),
+            operation.user_op_hash(&domain).unwrap()
+        );
+
+        let mut resigned = packed.clone();
+        resigned.signature = vec![];
+        assert_eq!(
+            resigned.user_op_hash(&domain).unwrap(),
+            packed.user_op_hash(&domain).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_hashes_eip7702_accounts_with_their_delegate() {
+        let domain = entry_point_v08_domain(1u64);
+        let delegate = Address::from(&[0xde; 20][..]);
+        let mut marker = [0u8; 20];
+        marker[..2].copy_from_slice(&EIP7702_MARKER);
+        let eip7702 = UserOperation {
+            factory: Some(Address::from(&marker[..])),
+            factory_data: vec![],
+            eip7702_delegate: Some(delegate),
+            ..operation()
+        };
+        assert!(eip7702.pack().is_eip7702());
+
+        // keccak(delegate) stands in for keccak(initCode)
+        let plain = UserOperation {
+            factory: Some(delegate),
+            eip7702_delegate: None,
+            ..eip7702.clone()
+        };
+        assert_eq!(
+            eip7702.user_op_hash(&domain).unwrap(),
+            plain.user_op_hash(&domain).unwrap()
+        );
+
+        // and keccak(delegate || initCode[20..]) when there's more
+        let with_data = UserOperation {
+            factory_data: vec![0x01, 0x02],
+            ..eip7702.clone()
+        };
+        let plain = UserOperation {
+            factory_data: vec![0x01, 0x02],
+            ..plain
+        };
+        assert_eq!(
+            with_data.user_op_hash(&domain).unwrap(),
+            plain.user_op_hash(&domain).unwrap()
+        );
+        let typed_data =
+            serde_json::from_value::<EIP712>(with_data.pack().typed_data(&domain)).unwrap();
+        assert_eq!(
+            hash_structured_data(typed_data).unwrap(),
+            with_data.user_op_hash(&domain).unwrap()
+        );
+
+        let unknown = UserOperation {
+            eip7702_delegate: None,
+            ..eip7702
+        };
+        assert_eq!(
+            unknown.user_op_hash(&domain).unwrap_err().kind(),
+            ErrorKind::MissingField("eip7702Delegate".into(), "PackedUserOperation".into())
+        );
+    }
+
+    #[test]
+    fn it_matches_hand_encoded_user_op_hashes() {
+        // keccak256(0x1901 || domainSeparator || keccak256(abi.encode(PACKED_USEROP_TYPEHASH, ...)))
+        // as in UserOperationLib.encode and EntryPoint.getUserOpHash, computed with a
+        // separate keccak and ABI encoding rather than this crate
+        let domain = entry_point_v08_domain(11_155_111u64);
+        assert_eq!(
+            format!("{:x}", operation().user_op_hash(&domain).unwrap()),
+            "15ce26b0ce01b8e6920222c14fbee95d5846292ed7c7ee00c2084c5f7e1ce0f7"
+        );
+
+        let mut marker = [0u8; 20];
+        marker[..2].copy_from_slice(&EIP7702_MARKER);
+        let eip7702 = UserOperation {
+            factory: Some(Address::from(&marker[..])),
+            eip7702_delegate: Some(Address::from(&[0xde; 20][..])),
+            ..operation()
+        };
+        assert_eq!(
+            format!("{:x}", eip7702.user_op_hash(&domain).unwrap()),
+            "fb0b04eebf03230492d9c3f233c354000a4ddfd694d5ec549dc733b5303c8e31"
+        );
+        let eip7702 = UserOperation {
+            factory_data: vec![],
+            ..eip7702
+        };
+        assert_eq!(
+            format!("{:x}", eip7702.user_op_hash(&domain).unwrap()),
+            "a0de41fbcddaf2c84b8f4c7d2b9d2c0f06e8abffd2499c45910d29890faf2519"
+        );
+    }
+}
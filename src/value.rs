@@ -0,0 +1,554 @@
+//! Typed EIP712 values
+use crate::eip712::MessageTypes;
+use crate::encode::check_hex;
+use crate::error::{serde_error, ErrorKind, Result};
+use crate::parser::{Parser, Type};
+use ethereum_types::{Address, U256};
+use indexmap::IndexMap;
+use rustc_hex::{FromHex, ToHex};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+
+/// A message value whose shape is known, either built programmatically or
+/// converted from JSON with `Eip712Value::from_json`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Eip712Value {
+    Address(Address),
+    /// bit width and value, e.g. `Uint(256, 1.into())`
+    Uint(u16, U256),
+    /// bit width and two's complement value
+    Int(u16, U256),
+    Bool(bool),
+    /// dynamic `bytes`
+    Bytes(Vec<u8>),
+    /// `bytes1` to `bytes32`
+    FixedBytes(Vec<u8>),
+    String(String),
+    Array(Vec<Eip712Value>),
+    /// type name and field values, in declaration order
+    Struct(String, IndexMap<String, Eip712Value>),
+}
+
+impl Eip712Value {
+    /// builds a struct value from its type name and fields
+    pub fn new_struct<I, S>(type_name: &str, fields: I) -> Self
+    where
+        I: IntoIterator<Item = (S, Eip712Value)>,
+        S: Into<String>,
+    {
+        Eip712Value::Struct(
+            type_name.to_owned(),
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.into(), value))
+                .collect(),
+        )
+    }
+
+    /// converts a JSON message into a value of `message_type`, checking every
+    /// field against `message_types`
+    pub fn from_json(
+        value: &Value,
+        message_type: &str,
+        message_types: &MessageTypes,
+    ) -> Result<Self> {
+        let parser = Parser::new();
+        let message_type = parser.parse_type(message_type)?;
+        from_json(&parser, &message_type, message_types, value, None)
+    }
+}
+
+pub(crate) fn from_json(
+    parser: &Parser,
+    message_type: &Type,
+    message_types: &MessageTypes,
+    value: &Value,
+    field_name: Option<&str>,
+) -> Result<Eip712Value> {
+    let converted = match message_type {
+        Type::Array { inner, length } => {
+            let values = value
+                .as_array()
+                .ok_or_else(|| serde_error("array", field_name))?;
+
+            // check if the type definition actually matches
+            // the length of items to be converted
+            if length.is_some() && Some(values.len() as u64) != *length {
+                let array_type = format!("{}[{}]", *inner, length.unwrap());
+                Err(ErrorKind::UnequalArrayItems(
+                    length.unwrap(),
+                    array_type,
+                    values.len() as u64,
+                ))?;
+            }
+
+            let items = values
+                .iter()
+                .map(|item| from_json(parser, inner, message_types, item, field_name))
+                .collect::<Result<_>>()?;
+            Eip712Value::Array(items)
+        }
+
+        Type::Custom(ref ident) if message_types.get(ident).is_some() => {
+            let object = value
+                .as_object()
+                .ok_or_else(|| serde_error(ident, field_name))?;
+            let mut fields = IndexMap::new();
+
+            for field in message_types
+                .get(ident)
+                .expect("Already checked in match guard; qed")
+            {
+                let value = object.get(&field.name).unwrap_or(&Value::Null);
+                let type_ = parser.parse_type(&field.type_)?;
+                let converted = from_json(parser, &type_, message_types, value, Some(&field.name))?;
+                fields.insert(field.name.clone(), converted);
+            }
+
+            Eip712Value::Struct(ident.clone(), fields)
+        }
+
+        Type::Bytes => Eip712Value::Bytes(parse_hex(value, field_name)?),
+
+        Type::Byte(len) => {
+            let bytes = parse_hex(value, field_name)?;
+            if bytes.len() > *len as usize {
+                Err(serde_error(&format!("{}", message_type), field_name))?;
+            }
+            Eip712Value::FixedBytes(bytes)
+        }
+
+        Type::String => Eip712Value::String(
+            value
+                .as_str()
+                .ok_or_else(|| serde_error("string", field_name))?
+                .to_owned(),
+        ),
+
+        Type::Bool => Eip712Value::Bool(
+            value
+                .as_bool()
+                .ok_or_else(|| serde_error("bool", field_name))?,
+        ),
+
+        Type::Address => {
+            let addr = value
+                .as_str()
+                .ok_or_else(|| serde_error("string", field_name))?;
+            if addr.len() != 42 {
+                Err(ErrorKind::InvalidAddressLength(addr.len()))?;
+            }
+            check_hex(addr)?;
+            let address = Address::from_str(&addr[2..])
+                .map_err(|err| ErrorKind::HexParseError(format!("{}", err)))?;
+            Eip712Value::Address(address)
+        }
+
+        Type::Uint(bits) | Type::Int(bits) => {
+            let string = value
+                .as_str()
+                .ok_or_else(|| serde_error("int/uint", field_name))?;
+
+            check_hex(string)?;
+            // more than 32 bytes can't be parsed into a word
+            if string.len() > 66 {
+                Err(ErrorKind::IntegerOutOfRange(
+                    field_name.unwrap_or("").to_owned(),
+                    format!("{}", message_type),
+                ))?;
+            }
+
+            let uint = U256::from_str(&string[2..])
+                .map_err(|err| ErrorKind::HexParseError(format!("{}", err)))?;
+
+            if let Type::Uint(_) = message_type {
+                Eip712Value::Uint(*bits, uint)
+            } else {
+                Eip712Value::Int(*bits, uint)
+            }
+        }
+
+        _ => {
+            return Err(ErrorKind::UnknownType(
+                field_name.unwrap_or("").to_string(),
+                format!("{}", *message_type),
+            ))?
+        }
+    };
+
+    Ok(converted)
+}
+
+fn parse_hex(value: &Value, field_name: Option<&str>) -> Result<Vec<u8>> {
+    let string = value
+        .as_str()
+        .ok_or_else(|| serde_error("string", field_name))?;
+
+    check_hex(string)?;
+
+    Ok(string[2..]
+        .from_hex::<Vec<u8>>()
+        .map_err(|err| ErrorKind::HexParseError(format!("{}", err)))?)
+}
+
+/// whether the two's complement `value` is negative
+pub(crate) fn is_negative(value: &U256) -> bool {
+    value.bit(255)
+}
+
+/// whether `value` is representable as `uint<bits>` (or `int<bits>` if `signed`)
+pub(crate) fn fits(value: &U256, bits: u16, signed: bool) -> bool {
+    let bits = bits as usize;
+    match (signed, is_negative(value)) {
+        (false, _) => value.bits() <= bits,
+        (true, false) => value.bits() < bits,
+        (true, true) => (!*value).bits() < bits,
+    }
+}
+
+impl From<Address> for Eip712Value {
+    fn from(address: Address) -> Self {
+        Eip712Value::Address(address)
+    }
+}
+
+impl From<U256> for Eip712Value {
+    fn from(uint: U256) -> Self {
+        Eip712Value::Uint(256, uint)
+    }
+}
+
+impl From<bool> for Eip712Value {
+    fn from(boolean: bool) -> Self {
+        Eip712Value::Bool(boolean)
+    }
+}
+
+impl From<String> for Eip712Value {
+    fn from(string: String) -> Self {
+        Eip712Value::String(string)
+    }
+}
+
+impl From<&str> for Eip712Value {
+    fn from(string: &str) -> Self {
+        Eip712Value::String(string.to_owned())
+    }
+}
+
+impl From<Vec<Eip712Value>> for Eip712Value {
+    fn from(items: Vec<Eip712Value>) -> Self {
+        Eip712Value::Array(items)
+    }
+}
+
+/// `{}` prints the value on a single line, `{:#}` indents nested structs and arrays
+impl fmt::Display for Eip712Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_value(self, f, 0)
+    }
+}
+
+fn write_value(value: &Eip712Value, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+    let pretty = f.alternate();
+    // separators after the opening bracket, between items and before the closing bracket
+    let (open_sep, sep, close_sep) = if pretty {
+        let indent = "    ".repeat(depth + 1);
+        (
+            format!("\n{}", indent),
+            format!(",\n{}", indent),
+            format!(",\n{}", "    ".repeat(depth)),
+        )
+    } else {
+        (" ".to_string(), ", ".to_string(), " ".to_string())
+    };
+    let (array_open, array_close) = if pretty {
+        (open_sep.as_str(), close_sep.as_str())
+    } else {
+        ("", "")
+    };
+
+    match value {
+        Eip712Value::Address(address) => write!(f, "0x{}", address.to_hex::<String>()),
+        Eip712Value::Uint(_, uint) => write!(f, "{}", uint),
+        Eip712Value::Int(_, int) if is_negative(int) => {
+            write!(f, "-{}", (!*int).overflowing_add(U256::one()).0)
+        }
+        Eip712Value::Int(_, int) => write!(f, "{}", int),
+        Eip712Value::Bool(boolean) => write!(f, "{}", boolean),
+        Eip712Value::Bytes(bytes) | Eip712Value::FixedBytes(bytes) => {
+            write!(f, "0x{}", bytes.to_hex::<String>())
+        }
+        Eip712Value::String(string) => write!(f, "{:?}", string),
+        Eip712Value::Array(items) if items.is_empty() => write!(f, "[]"),
+        Eip712Value::Array(items) => {
+            write!(f, "[{}", array_open)?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, "{}", sep)?;
+                }
+                write_value(item, f, depth + 1)?;
+            }
+            write!(f, "{}]", array_close)
+        }
+        Eip712Value::Struct(name, fields) if fields.is_empty() => write!(f, "{} {{}}", name),
+        Eip712Value::Struct(name, fields) => {
+            write!(f, "{} {{{}", name, open_sep)?;
+            for (i, (field, value)) in fields.iter().enumerate() {
+                if i > 0 {
+                    write!(f, "{}", sep)?;
+                }
+                write!(f, "{}: ", field)?;
+                write_value(value, f, depth + 1)?;
+            }
+            write!(f, "{}}}", close_sep)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eip712::{EIP712Domain, FieldType, EIP712};
+    use crate::encode::hash_structured_data;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn mail_types() -> MessageTypes {
+        let mut types = HashMap::new();
+        types.insert(
+            "Person".to_string(),
+            vec![
+                FieldType {
+                    name: "name".into(),
+                    type_: "string".into(),
+                },
+                FieldType {
+                    name: "wallets".into(),
+                    type_: "address[]".into(),
+                },
+            ],
+        );
+        types.insert(
+            "Mail".to_string(),
+            vec![
+                FieldType {
+                    name: "from".into(),
+                    type_: "Person".into(),
+                },
+                FieldType {
+                    name: "amount".into(),
+                    type_: "int64".into(),
+                },
+            ],
+        );
+        types
+    }
+
+    fn address(hex: &str) -> Address {
+        Address::from_str(hex).unwrap()
+    }
+
+    #[test]
+    fn it_converts_json_checked_against_types() {
+        let json = json!({
+            "from": {
+                "name": "Cow",
+                "wallets": ["0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"]
+            },
+            "amount": "0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"
+        });
+        let value = Eip712Value::from_json(&json, "Mail", &mail_types()).unwrap();
+
+        let expected = Eip712Value::new_struct(
+            "Mail",
+            vec![
+                (
+                    "from",
+                    Eip712Value::new_struct(
+                        "Person",
+                        vec![
+                            ("name", "Cow".into()),
+                            (
+                                "wallets",
+                                vec![address("CD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826").into()]
+                                    .into(),
+                            ),
+                        ],
+                    ),
+                ),
+                ("amount", Eip712Value::Int(64, U256::max_value())),
+            ],
+        );
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn it_reports_mismatches_during_conversion() {
+        let json = json!({ "from": { "name": "Cow", "wallets": "0x00" }, "amount": "0x1" });
+        let error = Eip712Value::from_json(&json, "Mail", &mail_types()).unwrap_err();
+        assert_eq!(
+            error.kind(),
+            ErrorKind::UnexpectedType("array".into(), "wallets".into())
+        );
+
+        let json = json!({ "from": { "name": "Cow", "wallets": [] } });
+        let error = Eip712Value::from_json(&json, "Mail", &mail_types()).unwrap_err();
+        assert_eq!(
+            error.kind(),
+            ErrorKind::UnexpectedType("int/uint".into(), "amount".into())
+        );
+    }
+
+    #[test]
+    fn it_pretty_prints() {
+        let value = Eip712Value::new_struct(
+            "Mail",
+            vec![
+                (
+                    "from",
+                    Eip712Value::new_struct(
+                        "Person",
+                        vec![
+                            ("name", "Cow".into()),
+                            (
+                                "wallets",
+                                vec![address("CD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826").into()]
+                                    .into(),
+                            ),
+                        ],
+                    ),
+                ),
+                ("amount", Eip712Value::Int(64, U256::max_value())),
+            ],
+        );
+
+        assert_eq!(
+            format!("{}", value),
+            "Mail { from: Person { name: \"Cow\", wallets: [0xcd2a3d9f938e13cd947ec05abc7fe734df8dd826] }, amount: -1 }"
+        );
+        assert_eq!(
+            format!("{:#}", value),
+            r#"Mail {
+    from: Person {
+        name: "Cow",
+        wallets: [
+            0xcd2a3d9f938e13cd947ec05abc7fe734df8dd826,
+        ],
+    },
+    amount: -1,
+}"#
+        );
+    }
+
+    #[test]
+    fn it_hashes_typed_values_like_json() {
+        let domain = EIP712Domain::new(
+            "AionRisingNFTs",
+            "0.0.1",
+            "0x7A69",
+            "0x037eDa3aDB1198021A9b2e88C22B464fD38db3f3",
+        );
+        let fields = vec![
+            FieldType {
+                name: "tokenId".into(),
+                type_: "uint256".into(),
+            },
+            FieldType {
+                name: "amount".into(),
+                type_: "uint256".into(),
+            },
+            FieldType {
+                name: "to".into(),
+                type_: "address".into(),
+            },
+            FieldType {
+                name: "nonce".into(),
+                type_: "uint256".into(),
+            },
+        ];
+        let data = EIP712::builder()
+            .domain(
+                "AionRisingNFTs",
+                "0.0.1",
+                "0x7A69",
+                "0x037eDa3aDB1198021A9b2e88C22B464fD38db3f3",
+            )
+            .custom_field(("NFTData".to_string(), fields))
+            .message(json!({
+                "tokenId": "0x1",
+                "amount": "0x1",
+                "to": "0x7FA9385bE102ac3EAc297483Dd6233D62b3e1496",
+                "nonce": "0x1"
+            }))
+            .build();
+
+        let value = Eip712Value::new_struct(
+            "NFTData",
+            vec![
+                ("tokenId", U256::from(1).into()),
+                ("amount", U256::from(1).into()),
+                (
+                    "to",
+                    address("7FA9385bE102ac3EAc297483Dd6233D62b3e1496").into(),
+                ),
+                ("nonce", U256::from(1).into()),
+            ],
+        );
+        assert_eq!(data.typed_message().unwrap(), value);
+        assert_eq!(
+            format!(
+                "{:x}",
+                hash_structured_data((&domain, &data.types, &value)).unwrap()
+            ),
+            "77915d20c811f39572463a234db9b776d518d07d9682a825be0d79752745a4c7"
+        );
+
+        let mut wrong = value.clone();
+        if let Eip712Value::Struct(_, fields) = &mut wrong {
+            fields.insert("to".into(), true.into());
+        }
+        assert_eq!(
+            hash_structured_data((&domain, &data.types, &wrong))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::UnexpectedType("address".into(), "to".into())
+        );
+    }
+
+    #[test]
+    fn it_rejects_out_of_range_integers() {
+        let mut types = HashMap::new();
+        types.insert(
+            "Vote".to_string(),
+            vec![FieldType {
+                name: "support".into(),
+                type_: "uint8".into(),
+            }],
+        );
+        let domain = EIP712Domain::new(
+            "Governor",
+            "1",
+            "0x1",
+            "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC",
+        );
+        let value = Eip712Value::from_json(&json!({ "support": "0x100" }), "Vote", &types).unwrap();
+        assert_eq!(
+            hash_structured_data((&domain, &types, &value))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::IntegerOutOfRange("support".into(), "uint8".into())
+        );
+    }
+
+    #[test]
+    fn it_checks_integer_ranges() {
+        assert!(fits(&U256::from(255), 8, false));
+        assert!(!fits(&U256::from(256), 8, false));
+        assert!(fits(&U256::from(127), 8, true));
+        assert!(!fits(&U256::from(128), 8, true));
+        assert!(fits(&!U256::from(127), 8, true), "-128");
+        assert!(!fits(&!U256::from(128), 8, true), "-129");
+    }
+}
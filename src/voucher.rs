@@ -0,0 +1,439 @@
+//! NFT vouchers for lazy minting: issue, sign, transport and verify
+use crate::deadline::{check_deadline, Clock};
+use crate::eip712::{EIP712Domain, FieldType, MessageTypes};
+use crate::error::{ErrorKind, Result};
+use crate::nft_helpers::MessageSchema;
+use crate::nonce::{NonceManager, NonceScope};
+use crate::signing::{address, recover, sign, Signature};
+use crate::solidity::generate_solidity;
+use crate::typed::{Eip712Struct, Eip712Type, StructuredData};
+use ethereum_types::{Address, H256, U256};
+use secp256k1::SecretKey;
+
+/// version byte of the compact encoding
+const ENCODING_VERSION: u8 = 1;
+
+/// The `NFTData` struct, see `MessageSchema::nft_data`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NftData {
+    pub token_id: U256,
+    pub amount: U256,
+    pub to: Address,
+    pub nonce: U256,
+}
+
+impl Eip712Struct for NftData {
+    fn type_name() -> String {
+        "NFTData".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        MessageSchema::nft_data().types["NFTData"].clone()
+    }
+
+    fn add_dependencies(_types: &mut MessageTypes) {}
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.token_id.encode_word(),
+            self.amount.encode_word(),
+            self.to.encode_word(),
+            self.nonce.encode_word(),
+        ]
+    }
+}
+
+/// An `NFTData` that may be redeemed until `expiry` (unix seconds, inclusive),
+/// signed as `NFTVoucher(NFTData data,uint256 expiry)NFTData(...)` so the
+/// expiry can't be stripped or extended.
+///
+/// This is a different on-chain type than a bare `NFTData`: the minting
+/// contract has to hash an `NFTVoucher`, see `Voucher::solidity`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Voucher {
+    pub data: NftData,
+    pub expiry: u64,
+}
+
+impl Voucher {
+    /// the `NFTVoucher` and `NFTData` structs, their typehashes and a verifier
+    /// contract for `domain`, see `solidity::generate_solidity`
+    pub fn solidity(domain: &EIP712Domain) -> Result<String> {
+        generate_solidity(&Self::message_types(), &Self::type_name(), domain)
+    }
+}
+
+impl Eip712Struct for Voucher {
+    fn type_name() -> String {
+        "NFTVoucher".into()
+    }
+
+    fn fields() -> Vec<FieldType> {
+        vec![
+            FieldType {
+                name: "data".to_string(),
+                type_: NftData::field_type(),
+            },
+            FieldType {
+                name: "expiry".to_string(),
+                type_: "uint256".to_string(),
+            },
+        ]
+    }
+
+    fn add_dependencies(types: &mut MessageTypes) {
+        NftData::add_field_types(types);
+    }
+
+    fn encode_data(&self) -> Vec<H256> {
+        vec![
+            self.data.encode_word(),
+            U256::from(self.expiry).encode_word(),
+        ]
+    }
+}
+
+/// A voucher together with the issuer's signature
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedVoucher {
+    pub voucher: Voucher,
+    pub signature: Signature,
+}
+
+impl SignedVoucher {
+    /// the EIP-712 digest the signature is over
+    pub fn digest(&self, domain: &EIP712Domain) -> H256 {
+        (domain, &self.voucher)
+            .digest()
+            .expect("typed structs always hash; qed")
+    }
+
+    /// 0x-prefixed hex of a versioned binary layout, integers are stored with
+    /// a length byte and without leading zeros
+    pub fn encode(&self) -> String {
+        let data = &self.voucher.data;
+        let mut bytes = vec![ENCODING_VERSION];
+        for uint in &[data.token_id, data.amount] {
+            push_uint(&mut bytes, uint);
+        }
+        bytes.extend_from_slice(&data.to.0);
+        push_uint(&mut bytes, &data.nonce);
+        bytes.extend_from_slice(&self.voucher.expiry.to_be_bytes());
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        format!("0x{}", hex::encode(bytes))
+    }
+
+    /// the inverse of `encode`
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let invalid = |reason: &str| ErrorKind::InvalidVoucherEncoding(reason.to_owned());
+        let hex = encoded
+            .strip_prefix("0x")
+            .ok_or_else(|| invalid("missing 0x prefix"))?;
+        let bytes = hex::decode(hex).map_err(|_| invalid("invalid hex"))?;
+        let mut reader = Reader(&bytes);
+
+        if reader.take(1)? != [ENCODING_VERSION] {
+            Err(invalid("unsupported version"))?;
+        }
+        let token_id = reader.uint()?;
+        let amount = reader.uint()?;
+        let to = Address::from(reader.take(20)?);
+        let nonce = reader.uint()?;
+        let mut expiry = [0u8; 8];
+        expiry.copy_from_slice(reader.take(8)?);
+        let signature = Signature::from_bytes(reader.take(65)?)?;
+        if !reader.0.is_empty() {
+            Err(invalid("trailing bytes"))?;
+        }
+
+        Ok(SignedVoucher {
+            voucher: Voucher {
+                data: NftData {
+                    token_id,
+                    amount,
+                    to,
+                    nonce,
+                },
+                expiry: u64::from_be_bytes(expiry),
+            },
+            signature,
+        })
+    }
+}
+
+fn push_uint(bytes: &mut Vec<u8>, uint: &U256) {
+    let mut word = [0u8; 32];
+    uint.to_big_endian(&mut word);
+    let significant = &word[32 - uint.bits().div_ceil(8)..];
+    bytes.push(significant.len() as u8);
+    bytes.extend_from_slice(significant);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            Err(ErrorKind::InvalidVoucherEncoding("unexpected end".into()))?;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn uint(&mut self) -> Result<U256> {
+        let len = self.take(1)?[0] as usize;
+        if len > 32 {
+            Err(ErrorKind::InvalidVoucherEncoding(
+                "integer longer than 32 bytes".into(),
+            ))?;
+        }
+        Ok(U256::from(self.take(len)?))
+    }
+}
+
+/// Issues vouchers for a domain, taking nonces from a `NonceManager`
+pub struct VoucherIssuer<N: NonceManager> {
+    domain: EIP712Domain,
+    secret_key: SecretKey,
+    nonces: N,
+}
+
+impl<N: NonceManager> VoucherIssuer<N> {
+    pub fn new(domain: EIP712Domain, secret_key: SecretKey, nonces: N) -> Self {
+        VoucherIssuer {
+            domain,
+            secret_key,
+            nonces,
+        }
+    }
+
+    /// the address vouchers are signed by
+    pub fn signer(&self) -> Address {
+        address(&self.secret_key)
+    }
+
+    pub fn issue_voucher(
+        &mut self,
+        token_id: U256,
+        amount: U256,
+        to: Address,
+        expiry: u64,
+    ) -> Result<SignedVoucher> {
+        let scope = NonceScope::new(self.signer(), to, &self.domain);
+        let voucher = Voucher {
+            data: NftData {
+                token_id,
+                amount,
+                to,
+                nonce: self.nonces.allocate(&scope)?,
+            },
+            expiry,
+        };
+        let digest = (&self.domain, &voucher)
+            .digest()
+            .expect("typed structs always hash; qed");
+
+        Ok(SignedVoucher {
+            voucher,
+            signature: sign(&digest, &self.secret_key),
+        })
+    }
+}
+
+/// Checks that `voucher` hasn't expired according to `clock` and was signed
+/// by one of `authorized_signers`, returning the signer
+pub fn verify_voucher<C: Clock>(
+    domain: &EIP712Domain,
+    voucher: &SignedVoucher,
+    authorized_signers: &[Address],
+    clock: &C,
+) -> Result<Address> {
+    check_deadline(
+        "expiry",
+        voucher.voucher.expiry.into(),
+        clock.now(),
+        clock.skew(),
+    )?;
+    let signer = recover(&voucher.digest(domain), &voucher.signature)?;
+    if !authorized_signers.contains(&signer) {
+        Err(ErrorKind::UnauthorizedSigner(format!("0x{:x}", signer)))?;
+    }
+    Ok(signer)
+}
+
+/// `verify_voucher`, additionally consuming the voucher's nonce so it can only
+/// be redeemed once
+pub fn redeem_voucher<C: Clock, N: NonceManager>(
+    domain: &EIP712Domain,
+    voucher: &SignedVoucher,
+    authorized_signers: &[Address],
+    clock: &C,
+    nonces: &mut N,
+) -> Result<Address> {
+    let signer = verify_voucher(domain, voucher, authorized_signers, clock)?;
+    let scope = NonceScope::new(signer, voucher.voucher.data.to, domain);
+    nonces.consume(&scope, voucher.voucher.data.nonce)?;
+    Ok(signer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deadline::{FakeClock, WithSkew};
+    use crate::eip712::EIP712;
+    use crate::encode::hash_structured_data;
+    use crate::nonce::{InMemoryNonceManager, NonceStrategy};
+    use crate::signing::test_key;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn domain() -> EIP712Domain {
+        EIP712Domain::new(
+            "AionRisingNFTs",
+            "0.0.1",
+            "0x7A69",
+            "0x037eDa3aDB1198021A9b2e88C22B464fD38db3f3",
+        )
+    }
+
+    fn issuer() -> VoucherIssuer<InMemoryNonceManager> {
+        let secret_key = test_key();
+        let mut nonces = InMemoryNonceManager::new(NonceStrategy::Sequential);
+        // start at 1 like the JSON examples
+        let scope = NonceScope::new(address(&secret_key), player(), &domain());
+        nonces.allocate(&scope).unwrap();
+        VoucherIssuer::new(domain(), secret_key, nonces)
+    }
+
+    fn player() -> Address {
+        "7FA9385bE102ac3EAc297483Dd6233D62b3e1496".parse().unwrap()
+    }
+
+    #[test]
+    fn it_hashes_nft_data_like_the_json_helpers() {
+        let data = NftData {
+            token_id: 1.into(),
+            amount: 1.into(),
+            to: player(),
+            nonce: 1.into(),
+        };
+
+        assert_eq!(
+            format!("{:x}", hash_structured_data((&domain(), &data)).unwrap()),
+            "77915d20c811f39572463a234db9b776d518d07d9682a825be0d79752745a4c7"
+        );
+    }
+
+    #[test]
+    fn it_signs_the_expiry_inside_the_voucher() {
+        let mut issuer = issuer();
+        let signed = issuer
+            .issue_voucher(7.into(), 1.into(), player(), 1_700_000_000)
+            .unwrap();
+
+        let untyped = EIP712::builder()
+            .typed_domain(domain())
+            .custom_field((Voucher::type_name(), Voucher::fields()))
+            .message(json!({
+                "data": {
+                    "tokenId": "0x7",
+                    "amount": "0x1",
+                    "to": "0x7FA9385bE102ac3EAc297483Dd6233D62b3e1496",
+                    "nonce": "0x1",
+                },
+                "expiry": "0x6553f100",
+            }));
+        let mut untyped = untyped.build();
+        untyped.add_type(NftData::type_name(), NftData::fields());
+
+        assert_eq!(
+            Voucher::encode_type(),
+            "NFTVoucher(NFTData data,uint256 expiry)\
+             NFTData(uint256 tokenId,uint256 amount,address to,uint256 nonce)"
+        );
+        assert_eq!(
+            signed.digest(&domain()),
+            hash_structured_data(untyped).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_generates_the_voucher_contract() {
+        let source = Voucher::solidity(&domain()).unwrap();
+
+        assert!(source.contains("struct NFTVoucher {\n    NFTData data;\n    uint256 expiry;\n}"));
+        assert!(source.contains("struct NFTData {"));
+        assert!(source.contains(&format!(
+            "bytes32 constant NFT_VOUCHER_TYPEHASH = 0x{:x};",
+            Voucher::type_hash()
+        )));
+        assert!(source.contains("_hashNFTData(data.data)"));
+        assert!(source.contains("contract NFTVoucherVerifier is EIP712"));
+    }
+
+    #[test]
+    fn it_runs_the_voucher_lifecycle() {
+        let mut issuer = issuer();
+        let first = issuer
+            .issue_voucher(7.into(), 1.into(), player(), 1_700_000_000)
+            .unwrap();
+        let second = issuer
+            .issue_voucher(8.into(), 2.into(), player(), 1_700_000_000)
+            .unwrap();
+        assert_eq!(first.voucher.data.nonce, 1.into());
+        assert_eq!(second.voucher.data.nonce, 2.into());
+
+        let encoded = first.encode();
+        assert_eq!(encoded.len(), 2 + 2 * (1 + 2 + 2 + 20 + 2 + 8 + 65));
+        let decoded = SignedVoucher::decode(&encoded).unwrap();
+        assert_eq!(decoded, first);
+
+        let signers = [issuer.signer()];
+        let clock = FakeClock::new(1_700_000_000);
+        assert_eq!(
+            verify_voucher(&domain(), &decoded, &signers, &clock).unwrap(),
+            issuer.signer()
+        );
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            verify_voucher(&domain(), &decoded, &signers, &clock)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::DeadlineExpired("expiry".into(), 1_700_000_000)
+        );
+        let skewed = WithSkew::new(&clock, Duration::from_secs(1));
+        verify_voucher(&domain(), &decoded, &signers, &skewed).unwrap();
+        let clock = FakeClock::new(0);
+        assert!(verify_voucher(&domain(), &decoded, &[player()], &clock).is_err());
+
+        let mut tampered = decoded.clone();
+        tampered.voucher.expiry += 1;
+        assert!(verify_voucher(&domain(), &tampered, &signers, &clock).is_err());
+
+        let mut redeemed = InMemoryNonceManager::new(NonceStrategy::Sequential);
+        redeem_voucher(&domain(), &decoded, &signers, &clock, &mut redeemed).unwrap();
+        assert_eq!(
+            redeem_voucher(&domain(), &decoded, &signers, &clock, &mut redeemed)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NonceAlreadyUsed("1".into())
+        );
+        redeem_voucher(&domain(), &second, &signers, &clock, &mut redeemed).unwrap();
+    }
+
+    #[test]
+    fn it_rejects_malformed_encodings() {
+        let encoded = issuer()
+            .issue_voucher(U256::max_value(), 1.into(), player(), u64::MAX)
+            .unwrap()
+            .encode();
+        assert!(SignedVoucher::decode(&encoded).is_ok());
+
+        assert!(SignedVoucher::decode(&encoded[2..]).is_err());
+        assert!(SignedVoucher::decode(&encoded[..encoded.len() - 2]).is_err());
+        assert!(SignedVoucher::decode(&format!("{}00", encoded)).is_err());
+        assert!(SignedVoucher::decode(&encoded.replacen("0x01", "0x02", 1)).is_err());
+        assert!(SignedVoucher::decode("0x0121").is_err());
+    }
+}
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: MIT
+// Generated by eip_712_utils, do not edit.
+pragma solidity ^0.8.20;
+
+import {EIP712} from "@openzeppelin/contracts/utils/cryptography/EIP712.sol";
+import {ECDSA} from "@openzeppelin/contracts/utils/cryptography/ECDSA.sol";
+
+struct Mail {
+    Person from;
+    Person[] to;
+    string contents;
+    bytes[2] attachments;
+}
+
+struct Person {
+    string name;
+    address[] wallets;
+}
+
+// keccak256("Mail(Person from,Person[] to,string contents,bytes[2] attachments)Person(string name,address[] wallets)")
+bytes32 constant MAIL_TYPEHASH = 0x0b7f566d184ca53e7944cacdba400ad21006cc6b43b448951f0e2d1396435432;
+
+// keccak256("Person(string name,address[] wallets)")
+bytes32 constant PERSON_TYPEHASH = 0xfabfe1ed996349fc6027709802be19d047da1aa5d6894ff5f6486d92db2e6860;
+
+/// @dev verifies signatures made for chainId 1 and verifyingContract 0xcccccccccccccccccccccccccccccccccccccccc
+contract MailVerifier is EIP712 {
+    constructor() EIP712("Ether \"Mail\"", "1") {}
+
+    function hashMail(Mail memory data) public view returns (bytes32) {
+        return _hashTypedDataV4(_hashMail(data));
+    }
+
+    function verifyMail(
+        Mail memory data,
+        bytes memory signature,
+        address signer
+    ) public view returns (bool) {
+        return ECDSA.recover(hashMail(data), signature) == signer;
+    }
+
+    function _hashMail(Mail memory data) internal pure returns (bytes32) {
+        return keccak256(
+            abi.encode(
+                MAIL_TYPEHASH,
+                _hashPerson(data.from),
+                _hashPersonArray(data.to),
+                keccak256(bytes(data.contents)),
+                _hashBytesArray2(data.attachments)
+            )
+        );
+    }
+
+    function _hashPerson(Person memory data) internal pure returns (bytes32) {
+        return keccak256(
+            abi.encode(
+                PERSON_TYPEHASH,
+                keccak256(bytes(data.name)),
+                keccak256(abi.encodePacked(data.wallets))
+            )
+        );
+    }
+
+    function _hashBytesArray2(bytes[2] memory items) internal pure returns (bytes32) {
+        bytes32[] memory hashes = new bytes32[](items.length);
+        for (uint256 i = 0; i < items.length; i++) {
+            hashes[i] = keccak256(items[i]);
+        }
+        return keccak256(abi.encodePacked(hashes));
+    }
+
+    function _hashPersonArray(Person[] memory items) internal pure returns (bytes32) {
+        bytes32[] memory hashes = new bytes32[](items.length);
+        for (uint256 i = 0; i < items.length; i++) {
+            hashes[i] = _hashPerson(items[i]);
+        }
+        return keccak256(abi.encodePacked(hashes));
+    }
+}
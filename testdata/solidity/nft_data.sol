@@ -0,0 +1,45 @@
+// SPDX-License-Identifier: MIT
+// Generated by eip_712_utils, do not edit.
+pragma solidity ^0.8.20;
+
+import {EIP712} from "@openzeppelin/contracts/utils/cryptography/EIP712.sol";
+import {ECDSA} from "@openzeppelin/contracts/utils/cryptography/ECDSA.sol";
+
+struct NFTData {
+    uint256 tokenId;
+    uint256 amount;
+    address to;
+    uint256 nonce;
+}
+
+// keccak256("NFTData(uint256 tokenId,uint256 amount,address to,uint256 nonce)")
+bytes32 constant NFT_DATA_TYPEHASH = 0x5eccad4b1a3c1194dba9c73a641fb7f2294d4e191631981162951b30717d2621;
+
+/// @dev verifies signatures made for chainId 31337 and verifyingContract 0x037eda3adb1198021a9b2e88c22b464fd38db3f3
+contract NFTDataVerifier is EIP712 {
+    constructor() EIP712("AionRisingNFTs", "0.0.1") {}
+
+    function hashNFTData(NFTData memory data) public view returns (bytes32) {
+        return _hashTypedDataV4(_hashNFTData(data));
+    }
+
+    function verifyNFTData(
+        NFTData memory data,
+        bytes memory signature,
+        address signer
+    ) public view returns (bool) {
+        return ECDSA.recover(hashNFTData(data), signature) == signer;
+    }
+
+    function _hashNFTData(NFTData memory data) internal pure returns (bytes32) {
+        return keccak256(
+            abi.encode(
+                NFT_DATA_TYPEHASH,
+                data.tokenId,
+                data.amount,
+                data.to,
+                data.nonce
+            )
+        );
+    }
+}
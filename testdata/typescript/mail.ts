@@ -0,0 +1,42 @@
+// Generated by eip_712_utils, do not edit.
+
+export const domain = {
+  name: "Ether \"Mail\"",
+  version: "1",
+  chainId: 1,
+  verifyingContract: "0xcccccccccccccccccccccccccccccccccccccccc",
+  salt: "0xabababababababababababababababababababababababababababababababab",
+} as const;
+
+export const types = {
+  Mail: [
+    { name: "from", type: "Person" },
+    { name: "to", type: "Person[]" },
+    { name: "contents", type: "string" },
+    { name: "attachments", type: "bytes[2]" },
+    { name: "priority", type: "uint8" },
+  ],
+  Person: [
+    { name: "name", type: "string" },
+    { name: "wallets", type: "address[]" },
+  ],
+} as const;
+
+export const primaryType = "Mail" as const;
+
+export interface Mail {
+  from: Person;
+  to: Person[];
+  contents: string;
+  attachments: [`0x${string}`, `0x${string}`];
+  priority: number;
+}
+
+export interface Person {
+  name: string;
+  wallets: `0x${string}`[];
+}
+
+export function typedData(message: Mail) {
+  return { domain, types, primaryType, message } as const;
+}
@@ -0,0 +1,30 @@
+// Generated by eip_712_utils, do not edit.
+
+export const domain = {
+  name: "AionRisingNFTs",
+  version: "0.0.1",
+  chainId: 31337,
+  verifyingContract: "0x037eda3adb1198021a9b2e88c22b464fd38db3f3",
+} as const;
+
+export const types = {
+  NFTData: [
+    { name: "tokenId", type: "uint256" },
+    { name: "amount", type: "uint256" },
+    { name: "to", type: "address" },
+    { name: "nonce", type: "uint256" },
+  ],
+} as const;
+
+export const primaryType = "NFTData" as const;
+
+export interface NFTData {
+  tokenId: bigint;
+  amount: bigint;
+  to: `0x${string}`;
+  nonce: bigint;
+}
+
+export function typedData(message: NFTData) {
+  return { domain, types, primaryType, message } as const;
+}